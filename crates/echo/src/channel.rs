@@ -4,11 +4,22 @@ use std::pin::Pin;
 use std::rc::Rc;
 use std::task::{Context, Poll, Waker};
 
+use crate::executor::{self, Waiting};
+use crate::instrument::{self, Event};
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod mt;
+pub mod watch;
+
+pub use self::broadcast::channel as broadcast;
+pub use self::watch::channel as watch;
+
 pub fn oneshot<T>() -> (Sender<T>, Receiver<T>) {
     let shared = Rc::new(RefCell::new(Shared::<T> {
-        value: None,
-        waker: None,
-        closed: false,
+        state: State::Empty,
+        rx_waker: None,
+        tx_waker: None,
     }));
 
     let tx = Sender::<T> {
@@ -19,7 +30,7 @@ pub fn oneshot<T>() -> (Sender<T>, Receiver<T>) {
         inner: shared.clone(),
     };
 
-    return (tx, rx);
+    (tx, rx)
 }
 
 pub struct Receiver<T> {
@@ -29,12 +40,11 @@ pub struct Receiver<T> {
 impl<T> Receiver<T> {
     pub fn try_recv(&self) -> Option<T> {
         let mut shared = self.inner.borrow_mut();
-        let value = shared.value.take();
-        match value {
-            None => None,
-            Some(x) => {
-                shared.closed = true;
-                Some(x)
+        match std::mem::replace(&mut shared.state, State::Received) {
+            State::Sent(value) => Some(value),
+            state => {
+                shared.state = state;
+                None
             }
         }
     }
@@ -45,27 +55,82 @@ pub struct Sender<T> {
 }
 
 struct Shared<T> {
-    value: Option<T>,
-    waker: Option<Waker>,
-    closed: bool,
+    state: State<T>,
+    rx_waker: Option<Waker>,
+    tx_waker: Option<Waker>,
+}
+
+/// Each side only ever moves the state forward, and wakes the other side when
+/// it does.
+enum State<T> {
+    /// Both sides are alive and nothing has been sent yet.
+    Empty,
+    /// The value is waiting for the receiver.
+    Sent(T),
+    /// The receiver took the value.
+    Received,
+    /// One side went away without the value getting through.
+    Closed,
 }
 
 impl<T> Sender<T> {
-    pub fn send(self, value: T) -> Result<()> {
+    /// Sends `value` to the receiver. Fails, handing the value back, if the
+    /// receiver has already been dropped.
+    pub fn send(self, value: T) -> std::result::Result<(), T> {
         let mut shared = self.inner.borrow_mut();
-        shared.value = Some(value);
-        if let Some(waker) = shared.waker.take() {
+        if !matches!(shared.state, State::Empty) {
+            return Err(value);
+        }
+
+        shared.state = State::Sent(value);
+        if let Some(waker) = shared.rx_waker.take() {
             waker.wake();
         }
 
         Ok(())
     }
+
+    /// Whether the receiver has been dropped, in which case sending is
+    /// pointless.
+    pub fn is_closed(&self) -> bool {
+        matches!(self.inner.borrow().state, State::Closed)
+    }
+
+    /// Waits for the receiver to be dropped, so that work producing the value
+    /// can be abandoned.
+    pub fn closed(&mut self) -> Closed<'_, T> {
+        Closed { sender: self }
+    }
 }
 
 impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
         let mut shared = self.inner.borrow_mut();
-        shared.closed = true;
+        if matches!(shared.state, State::Empty) {
+            shared.state = State::Closed;
+            if let Some(waker) = shared.rx_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Closed<'a, T> {
+    sender: &'a mut Sender<T>,
+}
+
+impl<T> Future for Closed<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.sender.inner.borrow_mut();
+        if matches!(shared.state, State::Closed) {
+            return Poll::Ready(());
+        }
+
+        shared.tx_waker = Some(cx.waker().clone());
+        executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+        Poll::Pending
     }
 }
 
@@ -74,25 +139,35 @@ impl<T> Future for Receiver<T> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut shared = self.inner.borrow_mut();
-        if let Some(value) = shared.value.take() {
-            shared.closed = true;
-            return Poll::Ready(Ok(value));
-        }
-
-        if shared.closed {
-            return Poll::Ready(Err(Error::ChannelClosed));
+        match std::mem::replace(&mut shared.state, State::Received) {
+            State::Sent(value) => Poll::Ready(Ok(value)),
+            State::Empty => {
+                shared.state = State::Empty;
+                shared.rx_waker = Some(cx.waker().clone());
+                executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+                Poll::Pending
+            }
+            state => {
+                shared.state = state;
+                Poll::Ready(Err(Error::ChannelClosed))
+            }
         }
-
-        shared.waker = Some(cx.waker().clone());
-        Poll::Pending
     }
 }
 
 impl<T> Drop for Receiver<T> {
     fn drop(&mut self) {
-        println!("Dropping receiver");
+        instrument::emit(Event::ReceiverDrop);
         let mut shared = self.inner.borrow_mut();
-        shared.closed = true;
+        let state = std::mem::replace(&mut shared.state, State::Closed);
+        if let Some(waker) = shared.tx_waker.take() {
+            waker.wake();
+        }
+
+        // An unreceived value may run arbitrary code when dropped, so it is
+        // dropped after the borrow ends.
+        drop(shared);
+        drop(state);
     }
 }
 
@@ -149,7 +224,7 @@ mod test {
     async fn test_channel_closed_after_drop_rx() {
         let (tx, rx) = oneshot::<usize>();
         drop(rx);
-        assert_eq!(Err(Error::ChannelClosed), tx.send(42));
+        assert_eq!(Err(42), tx.send(42));
     }
 
     #[tokio::test]
@@ -165,4 +240,33 @@ mod test {
         tx.send(42).unwrap();
         assert_eq!(42, rx.await.unwrap());
     }
+
+    #[test]
+    fn test_drop_tx_wakes_rx() {
+        let result = crate::runtime::run(async {
+            let (tx, rx) = oneshot::<usize>();
+            let waiting = executor::spawn(rx);
+            crate::time::sleep(std::time::Duration::from_millis(1)).await;
+            drop(tx);
+            waiting.await.unwrap()
+        })
+        .unwrap();
+        assert_eq!(Err(Error::ChannelClosed), result);
+    }
+
+    #[test]
+    fn test_sender_closed() {
+        crate::runtime::run(async {
+            let (mut tx, rx) = oneshot::<usize>();
+            assert!(!tx.is_closed());
+            let dropper = executor::spawn(async move {
+                crate::time::sleep(std::time::Duration::from_millis(1)).await;
+                drop(rx);
+            });
+            tx.closed().await;
+            assert!(tx.is_closed());
+            dropper.await.unwrap();
+        })
+        .unwrap();
+    }
 }
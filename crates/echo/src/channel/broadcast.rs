@@ -0,0 +1,341 @@
+//! A multi-producer, multi-consumer queue where every receiver sees every
+//! value.
+//!
+//! The channel keeps the last `capacity` values. Sending never waits: once the
+//! buffer is full the oldest value is dropped, and a receiver that hadn't seen
+//! it yet gets [`RecvError::Lagged`] with the number of values it missed before
+//! carrying on from the oldest one still buffered.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use super::mpsc::SendError;
+use crate::executor::{self, Waiting};
+
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        capacity > 0,
+        "broadcast channel capacity must be at least 1"
+    );
+    let shared = Rc::new(RefCell::new(Shared {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity,
+        head: 0,
+        senders: 1,
+        receivers: 0,
+        recv_wakers: HashMap::new(),
+        next_receiver: 0,
+    }));
+
+    let tx = Sender { inner: shared };
+    let rx = tx.subscribe();
+    (tx, rx)
+}
+
+struct Shared<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    /// Position of the first value in `buffer` in the stream of everything
+    /// ever sent.
+    head: u64,
+    senders: usize,
+    receivers: usize,
+    /// Receivers waiting for a value, keyed by receiver id.
+    recv_wakers: HashMap<u64, Waker>,
+    next_receiver: u64,
+}
+
+impl<T> Shared<T> {
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+
+    fn wake_receivers(&mut self) {
+        for (_, waker) in self.recv_wakers.drain() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Sender<T> {
+    inner: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` to every receiver, returning how many there are. Fails,
+    /// handing the value back, if there are none.
+    pub fn send(&self, value: T) -> std::result::Result<usize, SendError<T>> {
+        let mut shared = self.inner.borrow_mut();
+        if shared.receivers == 0 {
+            return Err(SendError(value));
+        }
+
+        let evicted = if shared.buffer.len() == shared.capacity {
+            shared.head += 1;
+            shared.buffer.pop_front()
+        } else {
+            None
+        };
+        shared.buffer.push_back(value);
+        shared.wake_receivers();
+
+        let receivers = shared.receivers;
+        drop(shared);
+        drop(evicted);
+        Ok(receivers)
+    }
+
+    /// A new receiver that will see every value sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut shared = self.inner.borrow_mut();
+        shared.receivers += 1;
+        let id = shared.next_receiver;
+        shared.next_receiver += 1;
+        Receiver {
+            inner: self.inner.clone(),
+            id,
+            next: shared.tail(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.inner.borrow().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.borrow_mut().senders += 1;
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.inner.borrow_mut();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            shared.wake_receivers();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Rc<RefCell<Shared<T>>>,
+    id: u64,
+    /// Position of the next value this receiver will see.
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Waits for the next value. Values still buffered are received even after
+    /// every sender has been dropped.
+    pub fn recv(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> std::result::Result<T, TryRecvError> {
+        let shared = self.inner.borrow();
+        if self.next < shared.head {
+            let missed = shared.head - self.next;
+            self.next = shared.head;
+            return Err(TryRecvError::Lagged(missed));
+        }
+
+        if self.next < shared.tail() {
+            let value = shared.buffer[(self.next - shared.head) as usize].clone();
+            self.next += 1;
+            return Ok(value);
+        }
+
+        if shared.senders == 0 {
+            return Err(TryRecvError::Closed);
+        }
+        Err(TryRecvError::Empty)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// A new receiver at the same position as this one.
+    pub fn resubscribe(&self) -> Self {
+        let mut shared = self.inner.borrow_mut();
+        shared.receivers += 1;
+        let id = shared.next_receiver;
+        shared.next_receiver += 1;
+        Self {
+            inner: self.inner.clone(),
+            id,
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut shared = self.inner.borrow_mut();
+        shared.receivers -= 1;
+        shared.recv_wakers.remove(&self.id);
+    }
+}
+
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T: Clone> Future for RecvFuture<'_, T> {
+    type Output = std::result::Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.receiver.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Lagged(missed)) => Poll::Ready(Err(RecvError::Lagged(missed))),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Empty) => {
+                let id = self.receiver.id;
+                let mut shared = self.receiver.inner.borrow_mut();
+                shared.recv_wakers.insert(id, cx.waker().clone());
+                executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender has been dropped and there is nothing left to receive.
+    Closed,
+    /// The receiver fell behind and this many values were dropped before it
+    /// saw them.
+    Lagged(u64),
+}
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecvError::Closed => write!(f, "Channel is closed"),
+            RecvError::Lagged(missed) => write!(f, "Receiver lagged behind by {} values", missed),
+        }
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+impl Display for TryRecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "Channel is empty"),
+            TryRecvError::Closed => write!(f, "Channel is closed"),
+            TryRecvError::Lagged(missed) => {
+                write!(f, "Receiver lagged behind by {} values", missed)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::runtime::Builder;
+    use crate::{executor, time};
+
+    #[test]
+    fn test_every_receiver_sees_every_value() {
+        let (tx, mut a) = channel::<usize>(8);
+        let mut b = tx.subscribe();
+        assert_eq!(Ok(2), tx.send(1));
+        tx.send(2).unwrap();
+        assert_eq!(Ok(1), a.try_recv());
+        assert_eq!(Ok(2), a.try_recv());
+        assert_eq!(Ok(1), b.try_recv());
+        assert_eq!(Ok(2), b.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), b.try_recv());
+    }
+
+    #[test]
+    fn test_lagged() {
+        let (tx, mut rx) = channel::<usize>(2);
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(Err(TryRecvError::Lagged(3)), rx.try_recv());
+        assert_eq!(Ok(3), rx.try_recv());
+        assert_eq!(Ok(4), rx.try_recv());
+    }
+
+    #[test]
+    fn test_subscribe_joins_at_tail() {
+        let (tx, mut early) = channel::<usize>(4);
+        tx.send(1).unwrap();
+        let mut late = tx.subscribe();
+        tx.send(2).unwrap();
+        assert_eq!(Ok(1), early.try_recv());
+        assert_eq!(Ok(2), late.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), late.try_recv());
+    }
+
+    #[test]
+    fn test_send_without_receivers() {
+        let (tx, rx) = channel::<usize>(1);
+        drop(rx);
+        assert_eq!(Err(SendError(1)), tx.send(1));
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let received = Builder::simulation(0)
+            .run(async {
+                let (tx, rx) = channel::<usize>(16);
+                let handles: Vec<_> = (0..3)
+                    .map(|_| {
+                        let mut rx = rx.resubscribe();
+                        executor::spawn(async move {
+                            let mut seen = Vec::new();
+                            loop {
+                                match rx.recv().await {
+                                    Ok(value) => seen.push(value),
+                                    Err(RecvError::Closed) => return seen,
+                                    Err(e) => panic!("unexpected error {}", e),
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                drop(rx);
+
+                time::sleep(Duration::from_millis(1)).await;
+                for i in 0..10 {
+                    tx.send(i).unwrap();
+                }
+                drop(tx);
+
+                let mut received = Vec::new();
+                for handle in handles {
+                    received.push(handle.await.unwrap());
+                }
+                received
+            })
+            .unwrap();
+
+        for seen in received {
+            assert_eq!((0..10).collect::<Vec<_>>(), seen);
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! Multi-producer, single-consumer queues.
+//!
+//! [`channel`] has a fixed capacity and `send` waits for room, which pushes
+//! back on producers that outpace the receiver. [`unbounded`] never waits and
+//! buffers as much as it is given. Senders waiting for room are served in FIFO
+//! order.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use super::{Error, Result};
+use crate::executor::{self, Waiting};
+
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be at least 1");
+    let shared = Rc::new(RefCell::new(Shared::new(Some(capacity))));
+    let tx = Sender {
+        inner: shared.clone(),
+    };
+    (tx, Receiver { inner: shared })
+}
+
+pub fn unbounded<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let shared = Rc::new(RefCell::new(Shared::new(None)));
+    let tx = UnboundedSender {
+        inner: shared.clone(),
+    };
+    (tx, Receiver { inner: shared })
+}
+
+struct Shared<T> {
+    queue: VecDeque<T>,
+    capacity: Option<usize>,
+    senders: usize,
+    closed: bool,
+    recv_waker: Option<Waker>,
+    /// Senders waiting for room, oldest first.
+    send_waiters: VecDeque<(u64, Waker)>,
+    next_waiter: u64,
+}
+
+impl<T> Shared<T> {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity,
+            senders: 1,
+            closed: false,
+            recv_waker: None,
+            send_waiters: VecDeque::new(),
+            next_waiter: 0,
+        }
+    }
+
+    fn has_room(&self) -> bool {
+        self.capacity.is_none_or(|cap| self.queue.len() < cap)
+    }
+
+    fn push(&mut self, value: T) {
+        self.queue.push_back(value);
+        if let Some(waker) = self.recv_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Lets the oldest waiting sender know it can try again. It stays at the
+    /// front of the queue until it has actually sent.
+    fn wake_next_sender(&self) {
+        if self.has_room()
+            && let Some((_, waker)) = self.send_waiters.front()
+        {
+            waker.wake_by_ref();
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity.unwrap() - self.queue.len()
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        for (_, waker) in self.send_waiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Closes the channel for good, returning whatever was left in it so that
+    /// it can be dropped once the state is no longer borrowed. Values may run
+    /// arbitrary code when dropped, including using the channel.
+    fn close_and_drain(&mut self) -> VecDeque<T> {
+        self.close();
+        std::mem::take(&mut self.queue)
+    }
+
+    fn add_sender(&mut self) {
+        self.senders += 1;
+    }
+
+    fn drop_sender(&mut self) {
+        self.senders -= 1;
+        if self.senders == 0
+            && let Some(waker) = self.recv_waker.take()
+        {
+            waker.wake();
+        }
+    }
+
+    fn send_unbounded(&mut self, value: T) -> std::result::Result<(), SendError<T>> {
+        if self.closed {
+            return Err(SendError(value));
+        }
+
+        self.push(value);
+        Ok(())
+    }
+
+    fn try_send(&mut self, value: T) -> std::result::Result<(), TrySendError<T>> {
+        if self.closed {
+            return Err(TrySendError::Closed(value));
+        }
+
+        // Don't jump ahead of senders that are already waiting.
+        if !self.has_room() || !self.send_waiters.is_empty() {
+            return Err(TrySendError::Full(value));
+        }
+
+        self.push(value);
+        Ok(())
+    }
+
+    /// Sends the value in `value` if there is room and no earlier sender is
+    /// waiting, otherwise queues up as `waiter` and leaves the value in place.
+    fn poll_send(
+        &mut self,
+        waiter: &mut Option<u64>,
+        value: &mut Option<T>,
+        cx: &Context<'_>,
+    ) -> Poll<std::result::Result<(), SendError<T>>> {
+        if self.closed {
+            let value = value.take().expect("send polled after completion");
+            return Poll::Ready(Err(SendError(value)));
+        }
+
+        let first_in_line = match self.send_waiters.front() {
+            None => true,
+            Some((id, _)) => Some(*id) == *waiter,
+        };
+
+        if self.has_room() && first_in_line {
+            if waiter.take().is_some() {
+                self.send_waiters.pop_front();
+            }
+            self.push(value.take().expect("send polled after completion"));
+            self.wake_next_sender();
+            return Poll::Ready(Ok(()));
+        }
+
+        match *waiter {
+            Some(id) => {
+                let slot = self.send_waiters.iter_mut().find(|(i, _)| *i == id);
+                if let Some((_, waker)) = slot
+                    && !waker.will_wake(cx.waker())
+                {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let id = self.next_waiter;
+                self.next_waiter += 1;
+                self.send_waiters.push_back((id, cx.waker().clone()));
+                *waiter = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Removes a sender that gave up waiting. It may have been woken for room
+    /// it never used, so that is passed on.
+    fn cancel_send(&mut self, waiter: u64) {
+        self.send_waiters.retain(|(i, _)| *i != waiter);
+        self.wake_next_sender();
+    }
+
+    fn try_recv(&mut self) -> std::result::Result<T, TryRecvError> {
+        if let Some(value) = self.queue.pop_front() {
+            self.wake_next_sender();
+            return Ok(value);
+        }
+
+        if self.closed || self.senders == 0 {
+            return Err(TryRecvError::Closed);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    fn poll_recv(&mut self, cx: &Context<'_>) -> Poll<Result<T>> {
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(Error::ChannelClosed)),
+            Err(TryRecvError::Empty) => {
+                self.recv_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct Sender<T> {
+    inner: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting for room in the channel if it is full. Fails,
+    /// handing the value back, if the receiver has been closed or dropped.
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            inner: &self.inner,
+            value: Some(value),
+            waiter: None,
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> std::result::Result<(), TrySendError<T>> {
+        self.inner.borrow_mut().try_send(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.borrow().is_closed()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.borrow().capacity()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.borrow_mut().add_sender();
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.borrow_mut().drop_sender();
+    }
+}
+
+pub struct SendFuture<'a, T> {
+    inner: &'a Rc<RefCell<Shared<T>>>,
+    value: Option<T>,
+    waiter: Option<u64>,
+}
+
+// The value is never pinned, it is only moved into the queue.
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = std::result::Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let poll = this
+            .inner
+            .borrow_mut()
+            .poll_send(&mut this.waiter, &mut this.value, cx);
+        if poll.is_pending() {
+            executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+        }
+        poll
+    }
+}
+
+impl<T> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            self.inner.borrow_mut().cancel_send(id);
+        }
+    }
+}
+
+pub struct UnboundedSender<T> {
+    inner: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Sends `value` without waiting. Fails, handing the value back, if the
+    /// receiver has been closed or dropped.
+    pub fn send(&self, value: T) -> std::result::Result<(), SendError<T>> {
+        self.inner.borrow_mut().send_unbounded(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.borrow().is_closed()
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.inner.borrow_mut().add_sender();
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.inner.borrow_mut().drop_sender();
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value. Returns `ChannelClosed` once every sender has
+    /// been dropped, or the receiver closed, and the queue has been drained.
+    pub fn recv(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> std::result::Result<T, TryRecvError> {
+        self.inner.borrow_mut().try_recv()
+    }
+
+    /// Stops any further sends. Values already in the channel can still be
+    /// received.
+    pub fn close(&mut self) {
+        self.inner.borrow_mut().close();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let rest = self.inner.borrow_mut().close_and_drain();
+        drop(rest);
+    }
+}
+
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = self.receiver.inner.borrow_mut().poll_recv(cx);
+        if poll.is_pending() {
+            executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+        }
+        poll
+    }
+}
+
+/// The receiver is gone. Holds the value that couldn't be sent.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Channel is closed")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> Display for TrySendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Channel is full"),
+            TrySendError::Closed(_) => write!(f, "Channel is closed"),
+        }
+    }
+}
+
+impl<T> std::error::Error for TrySendError<T> {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+impl Display for TryRecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "Channel is empty"),
+            TryRecvError::Closed => write!(f, "Channel is closed"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::runtime::Builder;
+    use crate::{executor, time};
+
+    #[tokio::test]
+    async fn test_send_recv() {
+        let (tx, mut rx) = channel::<usize>(4);
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(Ok(1), rx.recv().await);
+        assert_eq!(Ok(2), rx.recv().await);
+    }
+
+    #[tokio::test]
+    async fn test_closed_after_senders_drop() {
+        let (tx, mut rx) = unbounded::<usize>();
+        let tx2 = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        tx2.send(2).unwrap();
+        drop(tx2);
+        assert_eq!(Ok(1), rx.recv().await);
+        assert_eq!(Ok(2), rx.recv().await);
+        assert_eq!(Err(Error::ChannelClosed), rx.recv().await);
+    }
+
+    #[test]
+    fn test_try_send_full() {
+        let (tx, mut rx) = channel::<usize>(1);
+        tx.try_send(1).unwrap();
+        assert_eq!(Err(TrySendError::Full(2)), tx.try_send(2));
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), rx.try_recv());
+        tx.try_send(2).unwrap();
+    }
+
+    #[test]
+    fn test_close() {
+        let (tx, mut rx) = channel::<usize>(2);
+        tx.try_send(1).unwrap();
+        rx.close();
+        assert!(tx.is_closed());
+        assert_eq!(Err(TrySendError::Closed(2)), tx.try_send(2));
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Closed), rx.try_recv());
+    }
+
+    #[test]
+    fn test_send_after_receiver_drop() {
+        let (tx, rx) = unbounded::<usize>();
+        drop(rx);
+        assert_eq!(Err(SendError(7)), tx.send(7));
+    }
+
+    #[test]
+    fn test_backpressure() {
+        for seed in 0..10 {
+            let received = Builder::simulation(seed)
+                .run(async {
+                    let (tx, mut rx) = channel::<usize>(2);
+                    let sent = Rc::new(RefCell::new(0));
+                    let producers: Vec<_> = (0..3)
+                        .map(|p| {
+                            let (tx, sent) = (tx.clone(), sent.clone());
+                            executor::spawn(async move {
+                                for i in 0..10 {
+                                    tx.send(p * 100 + i).await.unwrap();
+                                    *sent.borrow_mut() += 1;
+                                }
+                            })
+                        })
+                        .collect();
+                    drop(tx);
+
+                    let mut received = Vec::new();
+                    loop {
+                        time::sleep(Duration::from_millis(1)).await;
+                        // Producers can only ever get `capacity` ahead.
+                        assert!(*sent.borrow() <= received.len() + 2);
+                        match rx.recv().await {
+                            Ok(value) => received.push(value),
+                            Err(_) => break,
+                        }
+                    }
+                    for producer in producers {
+                        producer.await.unwrap();
+                    }
+                    received
+                })
+                .unwrap();
+
+            assert_eq!(30, received.len());
+            for p in 0..3 {
+                let from_p: Vec<_> = received.iter().filter(|v| **v / 100 == p).collect();
+                assert!(from_p.is_sorted());
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocked_sender_fails_on_close() {
+        Builder::simulation(0)
+            .run(async {
+                let (tx, mut rx) = channel::<usize>(1);
+                tx.send(1).await.unwrap();
+                let blocked = executor::spawn(async move { tx.send(2).await });
+                time::sleep(Duration::from_millis(1)).await;
+                rx.close();
+                assert_eq!(Err(SendError(2)), blocked.await.unwrap());
+                assert_eq!(Ok(1), rx.recv().await);
+            })
+            .unwrap();
+    }
+}
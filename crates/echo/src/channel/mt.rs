@@ -0,0 +1,358 @@
+//! `Send` + `Sync` versions of the channels in [`crate::channel`], for talking
+//! to other threads, such as blocking workers or tasks on a multi-threaded
+//! runtime. The API matches the single threaded channels and errors are
+//! reported with the same [`Error`].
+//!
+//! Tasks on the echo runtime can wait on these while another thread sends, but
+//! only outside of a simulation, which can't see what other threads do.
+
+use std::cell::UnsafeCell;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use super::{Error, Result};
+use crate::executor::{self, Waiting};
+
+pub mod mpsc;
+
+pub fn oneshot<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: AtomicU8::new(EMPTY),
+        value: UnsafeCell::new(None),
+        rx_waker: AtomicWaker::new(),
+        tx_waker: AtomicWaker::new(),
+    });
+
+    let tx = Sender {
+        inner: inner.clone(),
+    };
+    (tx, Receiver { inner })
+}
+
+// The same states as the single threaded oneshot. Only the sender moves the
+// state out of `EMPTY` to `SENT`, and only while it owns `value`.
+const EMPTY: u8 = 0;
+const SENT: u8 = 1;
+const RECEIVED: u8 = 2;
+const CLOSED: u8 = 3;
+
+struct Inner<T> {
+    state: AtomicU8,
+    /// Written by the sender while the state is `EMPTY`, and read by the
+    /// receiver once it has seen `SENT`.
+    value: UnsafeCell<Option<T>>,
+    rx_waker: AtomicWaker,
+    tx_waker: AtomicWaker,
+}
+
+// Access to `value` is handed from one side to the other through `state`.
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` to the receiver. Fails, handing the value back, if the
+    /// receiver has already been dropped.
+    pub fn send(self, value: T) -> std::result::Result<(), T> {
+        let inner = &self.inner;
+        // SAFETY: the state is `EMPTY` or `CLOSED`, in which the receiver never
+        // touches the value.
+        unsafe { *inner.value.get() = Some(value) };
+
+        match inner
+            .state
+            .compare_exchange(EMPTY, SENT, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                inner.rx_waker.wake();
+                Ok(())
+            }
+            // SAFETY: the receiver closed the channel, so still never touches
+            // the value.
+            Err(_) => Err(unsafe { (*inner.value.get()).take().unwrap() }),
+        }
+    }
+
+    /// Whether the receiver has been dropped, in which case sending is
+    /// pointless.
+    pub fn is_closed(&self) -> bool {
+        self.inner.state.load(Ordering::Acquire) == CLOSED
+    }
+
+    /// Waits for the receiver to be dropped, so that work producing the value
+    /// can be abandoned.
+    pub fn closed(&mut self) -> Closed<'_, T> {
+        Closed { sender: self }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let closed =
+            self.inner
+                .state
+                .compare_exchange(EMPTY, CLOSED, Ordering::AcqRel, Ordering::Acquire);
+        if closed.is_ok() {
+            self.inner.rx_waker.wake();
+        }
+    }
+}
+
+pub struct Closed<'a, T> {
+    sender: &'a mut Sender<T>,
+}
+
+impl<T> Future for Closed<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &self.sender.inner;
+        if inner.state.load(Ordering::Acquire) == CLOSED {
+            return Poll::Ready(());
+        }
+
+        // Check again after registering, in case the receiver was dropped in
+        // between and its wake was missed.
+        inner.tx_waker.register(cx.waker());
+        if inner.state.load(Ordering::Acquire) == CLOSED {
+            return Poll::Ready(());
+        }
+
+        executor::record_wait(Waiting::Remote(std::any::type_name::<Self>()));
+        Poll::Pending
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Option<T> {
+        let inner = &self.inner;
+        inner
+            .state
+            .compare_exchange(SENT, RECEIVED, Ordering::AcqRel, Ordering::Acquire)
+            .ok()?;
+        // SAFETY: the sender is done with the value, and only the receiver
+        // that moved the state to `RECEIVED` gets here.
+        unsafe { (*inner.value.get()).take() }
+    }
+
+    fn poll_state(&self) -> Poll<Result<T>> {
+        if let Some(value) = self.try_recv() {
+            return Poll::Ready(Ok(value));
+        }
+
+        match self.inner.state.load(Ordering::Acquire) {
+            // A value sent since `try_recv` looked comes with a wake.
+            EMPTY | SENT => Poll::Pending,
+            _ => Poll::Ready(Err(Error::ChannelClosed)),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(res) = self.poll_state() {
+            return Poll::Ready(res);
+        }
+
+        self.inner.rx_waker.register(cx.waker());
+        if let Poll::Ready(res) = self.poll_state() {
+            return Poll::Ready(res);
+        }
+
+        executor::record_wait(Waiting::Remote(std::any::type_name::<Self>()));
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let inner = &self.inner;
+        if inner.state.swap(CLOSED, Ordering::AcqRel) == SENT {
+            // SAFETY: the value was sent but never received, so nobody else
+            // will touch it.
+            drop(unsafe { (*inner.value.get()).take() });
+        }
+        inner.tx_waker.wake();
+    }
+}
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 0b01;
+const WAKING: usize = 0b10;
+
+/// A waker slot that one side registers in and the other wakes, from any
+/// thread, without a lock. Only one task may register at a time.
+struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// `waker` is only touched by whoever moved `state` out of `WAITING`.
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+            .unwrap_or_else(|state| state)
+        {
+            WAITING => {
+                // SAFETY: we hold the `REGISTERING` bit, so a waker will only
+                // set `WAKING` and leave the slot to us.
+                let old = unsafe {
+                    let slot = &mut *self.waker.get();
+                    match slot {
+                        Some(old) if old.will_wake(waker) => None,
+                        _ => slot.replace(waker.clone()),
+                    }
+                };
+
+                let res = self.state.compare_exchange(
+                    REGISTERING,
+                    WAITING,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+                if res.is_err() {
+                    // Woken while registering: the waker skipped the slot, so
+                    // wake on its behalf.
+                    // SAFETY: we still hold the `REGISTERING` bit.
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+                drop(old);
+            }
+            // A wake is in progress and may have missed the new waker.
+            WAKING => waker.wake_by_ref(),
+            // Another register is in progress, which callers never do.
+            _ => {}
+        }
+    }
+
+    fn wake(&self) {
+        if self.state.fetch_or(WAKING, Ordering::AcqRel) == WAITING {
+            // SAFETY: we set `WAKING` while nobody was registering, so a
+            // register will now back off until the bit is cleared.
+            let waker = unsafe { (*self.waker.get()).take() };
+            self.state.fetch_and(!WAKING, Ordering::Release);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::runtime::Builder;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<Sender<Vec<u8>>>();
+        assert_send_sync::<Receiver<Vec<u8>>>();
+        assert_send_sync::<mpsc::Sender<Vec<u8>>>();
+        assert_send_sync::<mpsc::UnboundedSender<Vec<u8>>>();
+        assert_send_sync::<mpsc::Receiver<Vec<u8>>>();
+    }
+
+    #[test]
+    fn test_send_from_thread() {
+        let value = Builder::new()
+            .detect_deadlocks(true)
+            .run(async {
+                let (tx, rx) = oneshot::<usize>();
+                let worker = thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(20));
+                    tx.send(42).unwrap();
+                });
+                let value = rx.await.unwrap();
+                worker.join().unwrap();
+                value
+            })
+            .unwrap();
+        assert_eq!(42, value);
+    }
+
+    #[test]
+    fn test_sender_dropped_on_thread() {
+        let result = crate::runtime::run(async {
+            let (tx, rx) = oneshot::<usize>();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(tx);
+            });
+            rx.await
+        })
+        .unwrap();
+        assert_eq!(Err(Error::ChannelClosed), result);
+    }
+
+    #[test]
+    fn test_send_after_receiver_drop() {
+        let (tx, rx) = oneshot::<String>();
+        thread::spawn(move || drop(rx)).join().unwrap();
+        assert!(tx.is_closed());
+        assert_eq!(Err("hi".to_string()), tx.send("hi".to_string()));
+    }
+
+    #[test]
+    fn test_sender_closed_from_thread() {
+        crate::runtime::run(async {
+            let (mut tx, rx) = oneshot::<usize>();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(rx);
+            });
+            tx.closed().await;
+            assert!(tx.is_closed());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_racing_threads() {
+        for _ in 0..200 {
+            let (tx, rx) = oneshot::<Box<usize>>();
+            let sender = thread::spawn(move || tx.send(Box::new(7)).is_ok());
+            let receiver = thread::spawn(move || {
+                let value = rx.try_recv();
+                drop(rx);
+                value
+            });
+            let sent = sender.join().unwrap();
+            if let Some(value) = receiver.join().unwrap() {
+                assert!(sent);
+                assert_eq!(7, *value);
+            }
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! `Send` + `Sync` versions of [`crate::channel::mpsc`], built on atomics.
+//!
+//! Values go through a lock-free linked list that any number of senders push
+//! onto and the receiver pops from. One counter tracks how many values are in
+//! the channel along with whether it's closed, so a send is checked against
+//! both in a single step. Unlike the single threaded channel, senders waiting
+//! for room aren't served in order: all of them are woken when room is made,
+//! and race for it.
+
+use std::cell::UnsafeCell;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use super::AtomicWaker;
+use crate::channel::mpsc::{SendError, TryRecvError, TrySendError};
+use crate::channel::{Error, Result};
+use crate::executor::{self, Waiting};
+
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be at least 1");
+    let chan = Arc::new(Chan::new(Some(capacity)));
+    let tx = Sender {
+        inner: chan.clone(),
+    };
+    (tx, Receiver { inner: chan })
+}
+
+pub fn unbounded<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = Arc::new(Chan::new(None));
+    let tx = UnboundedSender {
+        inner: chan.clone(),
+    };
+    (tx, Receiver { inner: chan })
+}
+
+/// Set in [`Chan::state`] once nothing more can be sent.
+const CLOSED: usize = 1;
+/// What [`Chan::state`] goes up by for each value in the channel.
+const ONE: usize = 2;
+
+struct Chan<T> {
+    queue: Queue<T>,
+    /// How many values have been sent and not yet received, in steps of
+    /// [`ONE`], and whether the channel is [`CLOSED`]. A value is counted
+    /// before it is pushed onto the queue.
+    state: AtomicUsize,
+    capacity: Option<usize>,
+    senders: AtomicUsize,
+    rx_waker: AtomicWaker,
+    send_waiters: Waiters,
+}
+
+// The queue is only popped by the receiver, of which there is one.
+unsafe impl<T: Send> Send for Chan<T> {}
+unsafe impl<T: Send> Sync for Chan<T> {}
+
+impl<T> Chan<T> {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            queue: Queue::new(),
+            state: AtomicUsize::new(0),
+            capacity,
+            senders: AtomicUsize::new(1),
+            rx_waker: AtomicWaker::new(),
+            send_waiters: Waiters::new(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.load(Ordering::Acquire) & CLOSED != 0
+    }
+
+    fn try_send(&self, value: T) -> std::result::Result<(), TrySendError<T>> {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            if state & CLOSED != 0 {
+                return Err(TrySendError::Closed(value));
+            }
+            if self.capacity.is_some_and(|cap| state / ONE >= cap) {
+                return Err(TrySendError::Full(value));
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state + ONE,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        self.queue.push(value);
+        self.rx_waker.wake();
+        Ok(())
+    }
+
+    /// Only called by the receiver.
+    fn try_recv(&self) -> std::result::Result<T, TryRecvError> {
+        loop {
+            // SAFETY: there is only one receiver, and it doesn't pop from more
+            // than one place at once.
+            if let Some(value) = unsafe { self.queue.pop() } {
+                self.state.fetch_sub(ONE, Ordering::AcqRel);
+                self.send_waiters.wake_all();
+                return Ok(value);
+            }
+
+            let state = self.state.load(Ordering::Acquire);
+            if state / ONE == 0 {
+                return Err(if state & CLOSED != 0 {
+                    TryRecvError::Closed
+                } else {
+                    TryRecvError::Empty
+                });
+            }
+            // A sender has counted its value but not pushed it yet, which
+            // it's about to.
+            std::thread::yield_now();
+        }
+    }
+
+    fn poll_recv(&self, cx: &Context<'_>) -> Poll<Result<T>> {
+        let mut registered = false;
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Poll::Ready(Ok(value)),
+                Err(TryRecvError::Closed) => return Poll::Ready(Err(Error::ChannelClosed)),
+                Err(TryRecvError::Empty) if registered => return Poll::Pending,
+                // Check again after registering, in case a value was sent in
+                // between and its wake was missed.
+                Err(TryRecvError::Empty) => {
+                    self.rx_waker.register(cx.waker());
+                    registered = true;
+                }
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.state.fetch_or(CLOSED, Ordering::AcqRel);
+        self.send_waiters.wake_all();
+    }
+
+    fn add_sender(&self) {
+        self.senders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn drop_sender(&self) {
+        if self.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.state.fetch_or(CLOSED, Ordering::AcqRel);
+            self.rx_waker.wake();
+        }
+    }
+}
+
+pub struct Sender<T> {
+    inner: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting for room in the channel if it is full. Fails,
+    /// handing the value back, if the receiver has been closed or dropped.
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            inner: &self.inner,
+            value: Some(value),
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> std::result::Result<(), TrySendError<T>> {
+        self.inner.try_send(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity.unwrap() - self.inner.state.load(Ordering::Acquire) / ONE
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.add_sender();
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.drop_sender();
+    }
+}
+
+pub struct SendFuture<'a, T> {
+    inner: &'a Chan<T>,
+    value: Option<T>,
+}
+
+// The value is never pinned, it is only moved into the queue.
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = std::result::Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut value = self
+            .value
+            .take()
+            .expect("SendFuture polled after completion");
+        let mut registered = false;
+        loop {
+            match self.inner.try_send(value) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(value)) => return Poll::Ready(Err(SendError(value))),
+                Err(TrySendError::Full(v)) if registered => {
+                    self.value = Some(v);
+                    executor::record_wait(Waiting::Remote(std::any::type_name::<Self>()));
+                    return Poll::Pending;
+                }
+                // Check again after registering, in case room was made in
+                // between and its wake was missed.
+                Err(TrySendError::Full(v)) => {
+                    self.inner.send_waiters.push(cx.waker().clone());
+                    registered = true;
+                    value = v;
+                }
+            }
+        }
+    }
+}
+
+pub struct UnboundedSender<T> {
+    inner: Arc<Chan<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Sends `value` without waiting. Fails, handing the value back, if the
+    /// receiver has been closed or dropped.
+    pub fn send(&self, value: T) -> std::result::Result<(), SendError<T>> {
+        self.inner
+            .try_send(value)
+            .map_err(|e| SendError(e.into_inner()))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.inner.add_sender();
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.inner.drop_sender();
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value. Returns `ChannelClosed` once every sender has
+    /// been dropped, or the receiver closed, and the queue has been drained.
+    pub fn recv(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> std::result::Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// Stops any further sends. Values already in the channel can still be
+    /// received.
+    pub fn close(&mut self) {
+        self.inner.close();
+    }
+
+    /// Blocks the current thread until a value arrives. For use outside of
+    /// any runtime, such as on a blocking worker thread.
+    pub fn blocking_recv(&mut self) -> Result<T> {
+        let thread = std::thread::current();
+        let waker = Waker::from(Arc::new(ThreadWaker(thread)));
+        let cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(res) = self.inner.poll_recv(&cx) {
+                return res;
+            }
+            std::thread::park();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.close();
+        while self.inner.try_recv().is_ok() {}
+    }
+}
+
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = self.receiver.inner.poll_recv(cx);
+        if poll.is_pending() {
+            executor::record_wait(Waiting::Remote(std::any::type_name::<Self>()));
+        }
+        poll
+    }
+}
+
+struct ThreadWaker(std::thread::Thread);
+
+impl std::task::Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// An intrusive multi-producer, single-consumer queue. Senders swap their node
+/// in as the newest and then link the one before it to theirs, so the receiver
+/// may briefly see the newest values before an older one is linked in.
+struct Queue<T> {
+    /// The newest node, which senders swap themselves in as.
+    head: AtomicPtr<Node<T>>,
+    /// The node before the oldest value, only touched by the receiver. Starts
+    /// as an empty stub, and afterwards is the node last received from.
+    tail: UnsafeCell<*mut Node<T>>,
+}
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+        }))
+    }
+}
+
+impl<T> Queue<T> {
+    fn new() -> Self {
+        let stub = Node::new(None);
+        Self {
+            head: AtomicPtr::new(stub),
+            tail: UnsafeCell::new(stub),
+        }
+    }
+
+    fn push(&self, value: T) {
+        let node = Node::new(Some(value));
+        let prev = self.head.swap(node, Ordering::AcqRel);
+        // SAFETY: the receiver only frees a node once it has moved past it,
+        // which it can't do until this link is made.
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+    }
+
+    /// Takes the oldest value, if it has been linked in yet.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may pop at a time.
+    unsafe fn pop(&self) -> Option<T> {
+        // SAFETY: the tail and the nodes from it on belong to the receiver,
+        // apart from `next`, which senders write once.
+        unsafe {
+            let tail = *self.tail.get();
+            let next = (*tail).next.load(Ordering::Acquire);
+            if next.is_null() {
+                return None;
+            }
+            *self.tail.get() = next;
+            drop(Box::from_raw(tail));
+            (*next).value.take()
+        }
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        let mut node = *self.tail.get_mut();
+        while !node.is_null() {
+            // SAFETY: nothing else can reach the queue any more, and each node
+            // is freed once, after its `next` has been read.
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+/// The wakers of senders waiting for room, as a stack any thread can push to.
+/// They are only ever taken all at once, so a node is never freed while a
+/// sender might still be looking at it.
+struct Waiters {
+    head: AtomicPtr<WaiterNode>,
+}
+
+struct WaiterNode {
+    waker: Waker,
+    next: *mut WaiterNode,
+}
+
+impl Waiters {
+    fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn push(&self, waker: Waker) {
+        let node = Box::into_raw(Box::new(WaiterNode {
+            waker,
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            // SAFETY: the node isn't shared until the exchange succeeds.
+            unsafe { (*node).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Takes every waker on the stack, and hands each to `f`.
+    fn drain(&self, mut f: impl FnMut(Waker)) {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+        while !node.is_null() {
+            // SAFETY: the swap took the whole stack, so nothing else can reach
+            // these nodes.
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            f(boxed.waker);
+        }
+    }
+
+    fn wake_all(&self) {
+        self.drain(Waker::wake);
+    }
+}
+
+impl Drop for Waiters {
+    fn drop(&mut self) {
+        self.drain(drop);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_producers_on_threads() {
+        let received = crate::runtime::Builder::new()
+            .detect_deadlocks(true)
+            .run(async {
+                let (tx, mut rx) = channel::<usize>(4);
+                let producers: Vec<_> = (0..4)
+                    .map(|p| {
+                        let tx = tx.clone();
+                        thread::spawn(move || {
+                            for i in 0..100 {
+                                let mut value = p * 1000 + i;
+                                // No runtime on this thread, so spin on try_send.
+                                loop {
+                                    match tx.try_send(value) {
+                                        Ok(()) => break,
+                                        Err(TrySendError::Full(v)) => {
+                                            value = v;
+                                            thread::yield_now();
+                                        }
+                                        Err(TrySendError::Closed(_)) => panic!("closed"),
+                                    }
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                drop(tx);
+
+                let mut received = Vec::new();
+                while let Ok(value) = rx.recv().await {
+                    received.push(value);
+                }
+                for producer in producers {
+                    producer.join().unwrap();
+                }
+                received
+            })
+            .unwrap();
+
+        assert_eq!(400, received.len());
+        for p in 0..4 {
+            let from_p: Vec<_> = received.iter().filter(|v| **v / 1000 == p).collect();
+            assert_eq!(100, from_p.len());
+            assert!(from_p.is_sorted());
+        }
+    }
+
+    #[test]
+    fn test_blocking_recv_on_thread() {
+        let (tx, mut rx) = unbounded::<usize>();
+        let consumer = thread::spawn(move || {
+            let mut sum = 0;
+            while let Ok(value) = rx.blocking_recv() {
+                sum += value;
+            }
+            sum
+        });
+
+        crate::runtime::run(async move {
+            for i in 1..=10 {
+                tx.send(i).unwrap();
+                crate::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        })
+        .unwrap();
+        assert_eq!(55, consumer.join().unwrap());
+    }
+
+    #[test]
+    fn test_backpressure_across_threads() {
+        let (tx, mut rx) = channel::<usize>(1);
+        let consumer = thread::spawn(move || {
+            let mut seen = Vec::new();
+            while let Ok(value) = rx.blocking_recv() {
+                seen.push(value);
+            }
+            seen
+        });
+
+        crate::runtime::run(async move {
+            for i in 0..50 {
+                tx.send(i).await.unwrap();
+            }
+        })
+        .unwrap();
+        assert_eq!((0..50).collect::<Vec<_>>(), consumer.join().unwrap());
+    }
+
+    #[test]
+    fn test_send_after_close() {
+        let (tx, mut rx) = unbounded::<usize>();
+        rx.close();
+        let tx2 = tx.clone();
+        let res = thread::spawn(move || tx2.send(1)).join().unwrap();
+        assert_eq!(Err(SendError(1)), res);
+        assert!(tx.is_closed());
+    }
+
+    #[test]
+    fn test_wakers_can_use_the_channel() {
+        use std::sync::atomic::AtomicBool;
+        use std::task::Wake;
+
+        struct Probe(Sender<usize>, AtomicBool);
+
+        impl Wake for Probe {
+            fn wake(self: Arc<Self>) {
+                let _ = self.0.try_send(2);
+                self.1.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let (tx, mut rx) = channel(2);
+        let probe = Arc::new(Probe(tx.clone(), AtomicBool::new(false)));
+        let waker = Waker::from(probe.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut rx.recv()).poll(&mut cx).is_pending());
+
+        tx.try_send(1).unwrap();
+        assert!(probe.1.load(Ordering::SeqCst));
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Ok(2), rx.try_recv());
+    }
+
+    #[test]
+    fn test_unreceived_values_are_dropped() {
+        let value = Arc::new(());
+        let (tx, mut rx) = channel(4);
+        tx.try_send(value.clone()).unwrap();
+        tx.try_send(value.clone()).unwrap();
+        assert!(rx.try_recv().is_ok());
+        drop(rx);
+        assert_eq!(1, Arc::strong_count(&value));
+
+        let (tx, rx) = unbounded();
+        tx.send(value.clone()).unwrap();
+        drop(tx);
+        drop(rx);
+        assert_eq!(1, Arc::strong_count(&value));
+    }
+}
@@ -0,0 +1,296 @@
+//! A single value that receivers can watch for changes.
+//!
+//! Only the latest value is kept, so a receiver that falls behind skips
+//! straight to it, but every value carries a version and [`Receiver::changed`]
+//! always reports a send the receiver hasn't seen yet. Handy for state every
+//! task needs to follow, such as configuration or a shutdown flag.
+
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use super::mpsc::SendError;
+use super::{Error, Result};
+use crate::executor::{self, Waiting};
+
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Rc::new(Shared {
+        value: RefCell::new(initial),
+        state: RefCell::new(State {
+            version: 0,
+            closed: false,
+            receivers: 1,
+            recv_wakers: HashMap::new(),
+            next_receiver: 1,
+        }),
+    });
+
+    let tx = Sender {
+        inner: shared.clone(),
+    };
+    let rx = Receiver {
+        inner: shared,
+        id: 0,
+        seen: 0,
+    };
+    (tx, rx)
+}
+
+/// The value lives in its own cell so that a borrow of it, which callers can
+/// hold on to, doesn't stop receivers from registering for changes.
+struct Shared<T> {
+    value: RefCell<T>,
+    state: RefCell<State>,
+}
+
+struct State {
+    version: u64,
+    closed: bool,
+    receivers: usize,
+    recv_wakers: HashMap<u64, Waker>,
+    next_receiver: u64,
+}
+
+impl State {
+    fn wake_receivers(&mut self) {
+        for (_, waker) in self.recv_wakers.drain() {
+            waker.wake();
+        }
+    }
+
+    fn new_receiver<T>(&mut self, inner: &Rc<Shared<T>>, seen: u64) -> Receiver<T> {
+        self.receivers += 1;
+        let id = self.next_receiver;
+        self.next_receiver += 1;
+        Receiver {
+            inner: inner.clone(),
+            id,
+            seen,
+        }
+    }
+}
+
+pub struct Sender<T> {
+    inner: Rc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and notifies every receiver. Fails, handing the value
+    /// back, if there are no receivers left to see it.
+    pub fn send(&self, value: T) -> std::result::Result<(), SendError<T>> {
+        if self.inner.state.borrow().receivers == 0 {
+            return Err(SendError(value));
+        }
+
+        self.send_replace(value);
+        Ok(())
+    }
+
+    /// Replaces the value even if nobody is watching, returning the old one.
+    pub fn send_replace(&self, value: T) -> T {
+        let old = self.inner.value.replace(value);
+        self.notify();
+        old
+    }
+
+    /// Modifies the value in place and notifies every receiver.
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        modify(&mut self.inner.value.borrow_mut());
+        self.notify();
+    }
+
+    /// The current value. Panics if a new value is sent while it is held, so
+    /// don't keep it across an `.await`.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.inner.value.borrow()
+    }
+
+    /// A new receiver that has already seen the current value.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.inner.state.borrow_mut();
+        let version = state.version;
+        state.new_receiver(&self.inner, version)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.inner.state.borrow().receivers
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.receiver_count() == 0
+    }
+
+    fn notify(&self) {
+        let mut state = self.inner.state.borrow_mut();
+        state.version += 1;
+        state.wake_receivers();
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.borrow_mut();
+        state.closed = true;
+        state.wake_receivers();
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Rc<Shared<T>>,
+    id: u64,
+    /// The version of the last value this receiver has seen.
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    /// The current value, without marking it as seen. Panics if a new value is
+    /// sent while it is held, so don't keep it across an `.await`.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.inner.value.borrow()
+    }
+
+    /// The current value, marking it as seen.
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        self.seen = self.inner.state.borrow().version;
+        self.inner.value.borrow()
+    }
+
+    /// Whether a value has been sent since this receiver last looked. Fails
+    /// once the sender has been dropped.
+    pub fn has_changed(&self) -> Result<bool> {
+        let state = self.inner.state.borrow();
+        if state.closed {
+            return Err(Error::ChannelClosed);
+        }
+        Ok(state.version != self.seen)
+    }
+
+    /// Waits for a value this receiver hasn't seen and marks it as seen. Fails
+    /// once the sender has been dropped and every value has been seen.
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed { receiver: self }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.inner
+            .state
+            .borrow_mut()
+            .new_receiver(&self.inner, self.seen)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.borrow_mut();
+        state.receivers -= 1;
+        state.recv_wakers.remove(&self.id);
+    }
+}
+
+pub struct Changed<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Changed<'_, T> {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let receiver = &mut *self.receiver;
+        let mut state = receiver.inner.state.borrow_mut();
+        if state.version != receiver.seen {
+            receiver.seen = state.version;
+            return Poll::Ready(Ok(()));
+        }
+
+        if state.closed {
+            return Poll::Ready(Err(Error::ChannelClosed));
+        }
+
+        state.recv_wakers.insert(receiver.id, cx.waker().clone());
+        executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::runtime::Builder;
+    use crate::{executor, time};
+
+    #[test]
+    fn test_borrow_and_version() {
+        let (tx, mut rx) = channel(1);
+        assert_eq!(Ok(false), rx.has_changed());
+        tx.send(2).unwrap();
+        assert_eq!(Ok(true), rx.has_changed());
+        assert_eq!(2, *rx.borrow());
+        assert_eq!(Ok(true), rx.has_changed());
+        assert_eq!(2, *rx.borrow_and_update());
+        assert_eq!(Ok(false), rx.has_changed());
+    }
+
+    #[test]
+    fn test_send_without_receivers() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert_eq!(Err(SendError(2)), tx.send(2));
+        assert_eq!(1, tx.send_replace(3));
+        assert_eq!(3, *tx.borrow());
+    }
+
+    #[test]
+    fn test_closed() {
+        Builder::simulation(0)
+            .run(async {
+                let (tx, mut rx) = channel(0);
+                tx.send(1).unwrap();
+                drop(tx);
+                // The last value is still reported before the channel is closed.
+                assert_eq!(Ok(()), rx.changed().await);
+                assert_eq!(1, *rx.borrow());
+                assert_eq!(Err(Error::ChannelClosed), rx.changed().await);
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_signal() {
+        for seed in 0..10 {
+            let stopped = Builder::simulation(seed)
+                .run(async {
+                    let (tx, rx) = channel(false);
+                    let workers: Vec<_> = (0..4)
+                        .map(|_| {
+                            let mut shutdown = rx.clone();
+                            executor::spawn(async move {
+                                let mut ticks = 0;
+                                while !*shutdown.borrow_and_update() {
+                                    ticks += 1;
+                                    shutdown.changed().await.unwrap();
+                                }
+                                ticks
+                            })
+                        })
+                        .collect();
+
+                    time::sleep(Duration::from_millis(1)).await;
+                    tx.send_modify(|stop| *stop = true);
+                    let mut stopped = 0;
+                    for worker in workers {
+                        assert_eq!(1, worker.await.unwrap());
+                        stopped += 1;
+                    }
+                    stopped
+                })
+                .unwrap();
+            assert_eq!(4, stopped);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Turns byte streams into streams of frames and back, so protocols only have
+//! to describe how a single frame is laid out.
+//!
+//! A [`Decoder`] pulls frames out of the bytes read so far and an [`Encoder`]
+//! appends them to the bytes waiting to be written. [`FramedRead`],
+//! [`FramedWrite`] and [`Framed`] drive them over the echo IO traits.
+
+use std::io;
+
+mod bytes;
+mod framed;
+mod length_delimited;
+mod lines;
+
+pub use self::bytes::BytesCodec;
+pub use self::framed::{Framed, FramedRead, FramedWrite};
+pub use self::length_delimited::LengthDelimitedCodec;
+pub use self::lines::{LinesCodec, LinesCodecError};
+
+pub trait Decoder {
+    type Item;
+    type Error: From<io::Error>;
+
+    /// Takes a frame off the front of `src`, or returns `None` if it doesn't
+    /// hold a whole frame yet. Called again each time more data arrives.
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error>;
+
+    /// Like [`Decoder::decode`], but called once the stream has ended, until it
+    /// returns `None`. By default it fails if the stream ended partway
+    /// through a frame.
+    fn decode_eof(&mut self, src: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended partway through a frame",
+            )
+            .into()),
+        }
+    }
+}
+
+pub trait Encoder<Item> {
+    type Error: From<io::Error>;
+
+    /// Appends `item` to `dst`.
+    fn encode(&mut self, item: Item, dst: &mut Vec<u8>) -> Result<(), Self::Error>;
+}
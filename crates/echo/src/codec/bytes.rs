@@ -0,0 +1,35 @@
+use std::io;
+
+use super::{Decoder, Encoder};
+
+/// Passes bytes through as they arrive, without any framing. Each frame is
+/// whatever has been read since the last one.
+#[derive(Default)]
+pub struct BytesCodec;
+
+impl BytesCodec {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for BytesCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(std::mem::take(src)))
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for BytesCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, data: T, dst: &mut Vec<u8>) -> io::Result<()> {
+        dst.extend_from_slice(data.as_ref());
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use super::{Decoder, Encoder};
+use crate::io::{AsyncRead, AsyncWrite};
+use crate::stream::Stream;
+
+/// How much more to read at a time when the decoder needs more data.
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Sending buffers frames until this much is waiting, then writes them out.
+const BACKPRESSURE: usize = 16 * 1024;
+
+#[derive(Default)]
+struct ReadState {
+    buf: Vec<u8>,
+    eof: bool,
+    /// Set once the decoder has nothing more to give after the end of the
+    /// stream, or once reading has failed. Errors from `decode` aren't final,
+    /// as decoders like [`LinesCodec`](super::LinesCodec) skip past what they
+    /// reject, but at the end of the stream there's nothing more to move on to.
+    done: bool,
+}
+
+impl ReadState {
+    fn poll_next<R, D>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        decoder: &mut D,
+    ) -> Poll<Option<Result<D::Item, D::Error>>>
+    where
+        R: AsyncRead + ?Sized,
+        D: Decoder,
+    {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+
+            if self.eof {
+                let frame = decoder.decode_eof(&mut self.buf).transpose();
+                if !matches!(frame, Some(Ok(_))) {
+                    self.done = true;
+                }
+                return Poll::Ready(frame);
+            }
+
+            match decoder.decode(&mut self.buf) {
+                Ok(Some(frame)) => return Poll::Ready(Some(Ok(frame))),
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+
+            let len = self.buf.len();
+            self.buf.resize(len + READ_CHUNK, 0);
+            let res = reader.as_mut().poll_read(cx, &mut self.buf[len..]);
+            let n = match &res {
+                Poll::Ready(Ok(n)) => *n,
+                _ => 0,
+            };
+            self.buf.truncate(len + n);
+            match res {
+                Poll::Ready(Ok(0)) => self.eof = true,
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(e)) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct WriteState {
+    buf: Vec<u8>,
+}
+
+impl WriteState {
+    fn poll_flush<W: AsyncWrite + ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<io::Result<()>> {
+        while !self.buf.is_empty() {
+            let n = ready!(writer.as_mut().poll_write(cx, &self.buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.buf.drain(..n);
+        }
+        writer.poll_flush(cx)
+    }
+}
+
+/// Reads frames from an [`AsyncRead`] with a [`Decoder`].
+pub struct FramedRead<R, D> {
+    inner: R,
+    decoder: D,
+    state: ReadState,
+}
+
+impl<R: AsyncRead + Unpin, D: Decoder> FramedRead<R, D> {
+    pub fn new(inner: R, decoder: D) -> Self {
+        Self {
+            inner,
+            decoder,
+            state: ReadState::default(),
+        }
+    }
+
+    /// Returns the next frame, or `None` once the stream has ended and every
+    /// frame has been returned, or after a read error.
+    pub async fn next(&mut self) -> Option<Result<D::Item, D::Error>> {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<D::Item, D::Error>>> {
+        self.state
+            .poll_next(cx, Pin::new(&mut self.inner), &mut self.decoder)
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut D {
+        &mut self.decoder
+    }
+
+    /// The data that has been read but not yet decoded.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.state.buf
+    }
+
+    /// Returns the underlying reader. Anything not yet decoded is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin, D: Decoder + Unpin> Stream for FramedRead<R, D> {
+    type Item = Result<D::Item, D::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        FramedRead::poll_next(self.get_mut(), cx)
+    }
+}
+
+/// Writes frames to an [`AsyncWrite`] with an [`Encoder`].
+pub struct FramedWrite<W, E> {
+    inner: W,
+    encoder: E,
+    state: WriteState,
+}
+
+impl<W: AsyncWrite + Unpin, E> FramedWrite<W, E> {
+    pub fn new(inner: W, encoder: E) -> Self {
+        Self {
+            inner,
+            encoder,
+            state: WriteState::default(),
+        }
+    }
+
+    /// Encodes `item` and writes it out, along with anything fed before it.
+    pub async fn send<I>(&mut self, item: I) -> Result<(), E::Error>
+    where
+        E: Encoder<I>,
+    {
+        self.feed(item).await?;
+        Ok(self.flush().await?)
+    }
+
+    /// Encodes `item` without necessarily writing it out, so that several
+    /// frames can go out together. Only waits if too much is buffered.
+    pub async fn feed<I>(&mut self, item: I) -> Result<(), E::Error>
+    where
+        E: Encoder<I>,
+    {
+        if self.state.buf.len() >= BACKPRESSURE {
+            self.flush().await?;
+        }
+        self.encoder.encode(item, &mut self.state.buf)
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.state.poll_flush(cx, Pin::new(&mut self.inner))).await
+    }
+
+    /// Flushes, then shuts down the writer.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_shutdown(cx)).await
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    pub fn encoder_mut(&mut self) -> &mut E {
+        &mut self.encoder
+    }
+
+    /// The encoded frames that haven't been written yet.
+    pub fn write_buffer(&self) -> &[u8] {
+        &self.state.buf
+    }
+
+    /// Returns the underlying writer. Anything not yet written is lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads and writes frames over a single stream, with a codec that is both a
+/// [`Decoder`] and an [`Encoder`].
+pub struct Framed<T, U> {
+    inner: T,
+    codec: U,
+    read: ReadState,
+    write: WriteState,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin, U> Framed<T, U> {
+    pub fn new(inner: T, codec: U) -> Self {
+        Self {
+            inner,
+            codec,
+            read: ReadState::default(),
+            write: WriteState::default(),
+        }
+    }
+
+    /// Returns the next frame, or `None` once the stream has ended and every
+    /// frame has been returned, or after a read error.
+    pub async fn next(&mut self) -> Option<Result<U::Item, U::Error>>
+    where
+        U: Decoder,
+    {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<U::Item, U::Error>>>
+    where
+        U: Decoder,
+    {
+        self.read
+            .poll_next(cx, Pin::new(&mut self.inner), &mut self.codec)
+    }
+
+    /// Encodes `item` and writes it out, along with anything fed before it.
+    pub async fn send<I>(&mut self, item: I) -> Result<(), U::Error>
+    where
+        U: Encoder<I>,
+    {
+        self.feed(item).await?;
+        Ok(self.flush().await?)
+    }
+
+    /// Encodes `item` without necessarily writing it out, so that several
+    /// frames can go out together. Only waits if too much is buffered.
+    pub async fn feed<I>(&mut self, item: I) -> Result<(), U::Error>
+    where
+        U: Encoder<I>,
+    {
+        if self.write.buf.len() >= BACKPRESSURE {
+            self.flush().await?;
+        }
+        self.codec.encode(item, &mut self.write.buf)
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.write.poll_flush(cx, Pin::new(&mut self.inner))).await
+    }
+
+    /// Flushes, then shuts down the write side of the stream.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_shutdown(cx)).await
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn codec(&self) -> &U {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut U {
+        &mut self.codec
+    }
+
+    /// Returns the underlying stream. Anything buffered in either direction is
+    /// lost.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin, U: Decoder + Unpin> Stream for Framed<T, U> {
+    type Item = Result<U::Item, U::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Framed::poll_next(self.get_mut(), cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::codec::{LengthDelimitedCodec, LinesCodec};
+    use crate::executor;
+    use crate::runtime::Builder;
+    use crate::tcp::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_framed_read_stops_after_eof() {
+        let mut frames = FramedRead::new(&b"one\ntwo"[..], LinesCodec::new());
+        assert_eq!("one", frames.next().await.unwrap().unwrap());
+        assert_eq!("two", frames.next().await.unwrap().unwrap());
+        assert!(frames.next().await.is_none());
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_framed_read_carries_on_after_decode_error() {
+        let data = b"far too long\nok\n";
+        let mut frames = FramedRead::new(&data[..], LinesCodec::new_with_max_length(4));
+        assert!(frames.next().await.unwrap().is_err());
+        assert_eq!("ok", frames.next().await.unwrap().unwrap());
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_framed_read_stops_after_read_error() {
+        /// Fails once, then has a line to give.
+        struct FailsOnce(bool);
+
+        impl AsyncRead for FailsOnce {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                if std::mem::replace(&mut self.0, true) {
+                    buf[..3].copy_from_slice(b"ok\n");
+                    return Poll::Ready(Ok(3));
+                }
+                Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+            }
+        }
+
+        let mut frames = FramedRead::new(FailsOnce(false), LinesCodec::new());
+        assert!(frames.next().await.unwrap().is_err());
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_framed_write_batches_fed_frames() {
+        let mut frames = FramedWrite::new(Vec::new(), LinesCodec::new());
+        frames.feed("one").await.unwrap();
+        frames.feed("two").await.unwrap();
+        assert!(frames.get_ref().is_empty());
+        frames.send("three").await.unwrap();
+        assert_eq!(b"one\ntwo\nthree\n", &frames.get_ref()[..]);
+    }
+
+    #[test]
+    fn test_framed_over_sim_tcp() {
+        let replies = Builder::simulation(3)
+            .run(async {
+                let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+                let addr = listener.local_addr()?;
+                let server = executor::spawn(async move {
+                    let (stream, _) = listener.accept().await?;
+                    let mut frames = Framed::new(stream, LengthDelimitedCodec::new());
+                    while let Some(frame) = frames.next().await {
+                        let mut frame = frame?;
+                        frame.reverse();
+                        frames.send(frame).await?;
+                    }
+                    frames.close().await
+                });
+
+                let stream = TcpStream::connect(addr).await?;
+                let mut frames = Framed::new(stream, LengthDelimitedCodec::new());
+                let mut replies = Vec::new();
+                for msg in ["hello", "", "world"] {
+                    frames.send(msg.as_bytes()).await?;
+                    replies.push(frames.next().await.unwrap()?);
+                }
+                frames.close().await?;
+                assert!(frames.next().await.is_none());
+                server.await.unwrap()?;
+                io::Result::Ok(replies)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec![b"olleh".to_vec(), vec![], b"dlrow".to_vec()], replies);
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+
+use super::{Decoder, Encoder};
+
+/// Frames that start with their length, as an unsigned integer. The length
+/// doesn't include the header itself.
+///
+/// By default the header is 4 bytes, big endian, and frames are limited to
+/// 8 MiB.
+pub struct LengthDelimitedCodec {
+    length_field_length: usize,
+    big_endian: bool,
+    max_frame_length: usize,
+    /// The length of the frame being decoded, once its header has been read.
+    pending: Option<usize>,
+}
+
+impl LengthDelimitedCodec {
+    pub fn new() -> Self {
+        Self {
+            length_field_length: 4,
+            big_endian: true,
+            max_frame_length: 8 * 1024 * 1024,
+            pending: None,
+        }
+    }
+
+    /// Sets the size of the length header in bytes, which must be between 1
+    /// and 8.
+    pub fn length_field_length(mut self, len: usize) -> Self {
+        assert!(
+            (1..=8).contains(&len),
+            "length field must be 1 to 8 bytes, not {len}"
+        );
+        self.length_field_length = len;
+        self
+    }
+
+    pub fn big_endian(mut self) -> Self {
+        self.big_endian = true;
+        self
+    }
+
+    pub fn little_endian(mut self) -> Self {
+        self.big_endian = false;
+        self
+    }
+
+    /// Frames longer than this fail with `InvalidData`, both ways.
+    pub fn max_frame_length(mut self, len: usize) -> Self {
+        self.max_frame_length = len;
+        self
+    }
+
+    fn too_long(&self, len: u64) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame of {len} bytes is longer than the maximum of {}",
+                self.max_frame_length
+            ),
+        )
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let header = self.length_field_length;
+        let len = match self.pending {
+            Some(len) => len,
+            None => {
+                if src.len() < header {
+                    return Ok(None);
+                }
+
+                let mut bytes = [0u8; 8];
+                let len = if self.big_endian {
+                    bytes[8 - header..].copy_from_slice(&src[..header]);
+                    u64::from_be_bytes(bytes)
+                } else {
+                    bytes[..header].copy_from_slice(&src[..header]);
+                    u64::from_le_bytes(bytes)
+                };
+                if len > self.max_frame_length as u64 {
+                    return Err(self.too_long(len));
+                }
+
+                src.drain(..header);
+                self.pending = Some(len as usize);
+                len as usize
+            }
+        };
+
+        if src.len() < len {
+            src.reserve(len - src.len());
+            return Ok(None);
+        }
+
+        self.pending = None;
+        Ok(Some(src.drain(..len).collect()))
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LengthDelimitedCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: T, dst: &mut Vec<u8>) -> io::Result<()> {
+        let frame = frame.as_ref();
+        let len = frame.len() as u64;
+        let header = self.length_field_length;
+        if frame.len() > self.max_frame_length || (header < 8 && len >> (header * 8) != 0) {
+            return Err(self.too_long(len));
+        }
+
+        if self.big_endian {
+            dst.extend_from_slice(&len.to_be_bytes()[8 - header..]);
+        } else {
+            dst.extend_from_slice(&len.to_le_bytes()[..header]);
+        }
+        dst.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_in_pieces() {
+        let mut codec = LengthDelimitedCodec::new();
+        let mut wire = Vec::new();
+        codec.encode(b"hello", &mut wire).unwrap();
+        codec.encode(b"", &mut wire).unwrap();
+        assert_eq!(b"\0\0\0\x05hello\0\0\0\0", &wire[..]);
+
+        let mut buf = Vec::new();
+        let mut frames = Vec::new();
+        for byte in wire {
+            buf.push(byte);
+            while let Some(frame) = codec.decode(&mut buf).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(vec![b"hello".to_vec(), vec![]], frames);
+    }
+
+    #[test]
+    fn test_header_width_and_endianness() {
+        let mut codec = LengthDelimitedCodec::new()
+            .length_field_length(2)
+            .little_endian();
+        let mut wire = Vec::new();
+        codec.encode(vec![7; 258], &mut wire).unwrap();
+        assert_eq!([2, 1], wire[..2]);
+        assert_eq!(vec![7; 258], codec.decode(&mut wire).unwrap().unwrap());
+
+        let mut codec = LengthDelimitedCodec::new().length_field_length(1);
+        let err = codec.encode(vec![0; 256], &mut wire).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_max_frame_length() {
+        let mut codec = LengthDelimitedCodec::new().max_frame_length(4);
+        let mut buf = b"\0\0\0\x05hello".to_vec();
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(codec.encode(b"hello", &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::io;
+
+use super::{Decoder, Encoder};
+
+/// Splits a stream into lines of UTF-8 text, without their `\n` or `\r\n`.
+/// Lines are written with a `\n`.
+pub struct LinesCodec {
+    max_length: usize,
+    /// Where to carry on looking for the end of the line, so that a long line
+    /// arriving in pieces isn't searched from the start each time.
+    next_index: usize,
+    /// Set after an overlong line has been reported, until the rest of it has
+    /// been skipped.
+    discarding: bool,
+}
+
+impl LinesCodec {
+    /// A codec that accepts lines of any length.
+    pub fn new() -> Self {
+        Self::new_with_max_length(usize::MAX)
+    }
+
+    /// A codec that fails with [`LinesCodecError::MaxLineLengthExceeded`] on
+    /// lines longer than `max_length`, not counting the line ending, rather
+    /// than buffering them. The rest of an overlong line is skipped, so
+    /// decoding can carry on with the next one.
+    pub fn new_with_max_length(max_length: usize) -> Self {
+        Self {
+            max_length,
+            next_index: 0,
+            discarding: false,
+        }
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+}
+
+impl Default for LinesCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn utf8(line: Vec<u8>) -> Result<String, LinesCodecError> {
+    String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+    type Error = LinesCodecError;
+
+    fn decode(&mut self, src: &mut Vec<u8>) -> Result<Option<String>, LinesCodecError> {
+        loop {
+            // Leave room for a `\r\n` past the limit.
+            let limit = self.max_length.saturating_add(2);
+            let end = src.len().min(limit);
+            let newline = src[self.next_index..end].iter().position(|b| *b == b'\n');
+
+            match (self.discarding, newline) {
+                (true, Some(i)) => {
+                    src.drain(..self.next_index + i + 1);
+                    self.next_index = 0;
+                    self.discarding = false;
+                }
+                (true, None) => {
+                    src.drain(..end);
+                    self.next_index = 0;
+                    if src.is_empty() {
+                        return Ok(None);
+                    }
+                }
+                (false, Some(i)) => {
+                    let mut line: Vec<u8> = src.drain(..self.next_index + i + 1).collect();
+                    self.next_index = 0;
+                    line.pop();
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    if line.len() > self.max_length {
+                        return Err(LinesCodecError::MaxLineLengthExceeded);
+                    }
+                    return utf8(line).map(Some);
+                }
+                (false, None) if src.len() >= limit => {
+                    self.next_index = 0;
+                    self.discarding = true;
+                    return Err(LinesCodecError::MaxLineLengthExceeded);
+                }
+                (false, None) => {
+                    self.next_index = end;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// The last line doesn't need a line ending.
+    fn decode_eof(&mut self, src: &mut Vec<u8>) -> Result<Option<String>, LinesCodecError> {
+        if let Some(line) = self.decode(src)? {
+            return Ok(Some(line));
+        }
+        self.next_index = 0;
+        if src.is_empty() || self.discarding {
+            src.clear();
+            return Ok(None);
+        }
+        utf8(std::mem::take(src)).map(Some)
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for LinesCodec {
+    type Error = LinesCodecError;
+
+    fn encode(&mut self, line: T, dst: &mut Vec<u8>) -> Result<(), LinesCodecError> {
+        dst.extend_from_slice(line.as_ref().as_bytes());
+        dst.push(b'\n');
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum LinesCodecError {
+    MaxLineLengthExceeded,
+    Io(io::Error),
+}
+
+impl fmt::Display for LinesCodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxLineLengthExceeded => write!(f, "line is longer than the maximum length"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for LinesCodecError {}
+
+impl From<io::Error> for LinesCodecError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_in_pieces() {
+        let mut codec = LinesCodec::new();
+        let mut buf = b"hel".to_vec();
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"lo\r\nworld\nrest");
+        assert_eq!("hello", codec.decode(&mut buf).unwrap().unwrap());
+        assert_eq!("world", codec.decode(&mut buf).unwrap().unwrap());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!("rest", codec.decode_eof(&mut buf).unwrap().unwrap());
+        assert!(codec.decode_eof(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_max_length_skips_long_lines() {
+        let mut codec = LinesCodec::new_with_max_length(4);
+        let mut buf = b"abcd\r\nabcdefgh".to_vec();
+        assert_eq!("abcd", codec.decode(&mut buf).unwrap().unwrap());
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(LinesCodecError::MaxLineLengthExceeded)
+        ));
+
+        // The rest of the long line is skipped as it arrives.
+        buf.extend_from_slice(b"ijklmnop");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"qrs\nok\n");
+        assert_eq!("ok", codec.decode(&mut buf).unwrap().unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut codec = LinesCodec::new();
+        let mut buf = b"\xff\nok\n".to_vec();
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(LinesCodecError::Io(_))
+        ));
+        assert_eq!("ok", codec.decode(&mut buf).unwrap().unwrap());
+    }
+}
@@ -0,0 +1,277 @@
+//! Adapters between echo's IO traits and tokio's.
+//!
+//! [`TokioCompat`] implements tokio's traits for anything implementing echo's,
+//! and [`EchoCompat`] echo's traits for anything implementing tokio's, so
+//! either side can be handed to code written against the other. Only the
+//! traits are adapted: tokio types that need the tokio runtime, like
+//! `tokio::net::TcpStream`, still need it underneath.
+//!
+//! The extension traits come in a read and a write flavour for each direction,
+//! as one blanket impl can't cover both readers and writers.
+
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::ReadBuf;
+
+use crate::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+macro_rules! compat_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug)]
+        pub struct $name<T> {
+            inner: T,
+        }
+
+        impl<T> $name<T> {
+            pub fn new(inner: T) -> Self {
+                Self { inner }
+            }
+
+            pub fn get_ref(&self) -> &T {
+                &self.inner
+            }
+
+            pub fn get_mut(&mut self) -> &mut T {
+                &mut self.inner
+            }
+
+            pub fn into_inner(self) -> T {
+                self.inner
+            }
+        }
+    };
+}
+
+compat_type! {
+    /// Wraps an echo IO object so that it implements tokio's traits.
+    TokioCompat
+}
+
+compat_type! {
+    /// Wraps a tokio IO object so that it implements echo's traits.
+    EchoCompat
+}
+
+/// Wraps echo readers, and anything that also writes, for use with tokio.
+pub trait EchoCompatExt: AsyncRead + Sized {
+    fn tokio_compat(self) -> TokioCompat<Self> {
+        TokioCompat::new(self)
+    }
+}
+
+impl<T: AsyncRead> EchoCompatExt for T {}
+
+/// Wraps echo writers for use with tokio.
+pub trait EchoWriteCompatExt: AsyncWrite + Sized {
+    fn tokio_compat_write(self) -> TokioCompat<Self> {
+        TokioCompat::new(self)
+    }
+}
+
+impl<T: AsyncWrite> EchoWriteCompatExt for T {}
+
+/// Wraps tokio readers, and anything that also writes, for use with echo.
+pub trait TokioCompatExt: tokio::io::AsyncRead + Sized {
+    fn echo_compat(self) -> EchoCompat<Self> {
+        EchoCompat::new(self)
+    }
+}
+
+impl<T: tokio::io::AsyncRead> TokioCompatExt for T {}
+
+/// Wraps tokio writers for use with echo.
+pub trait TokioWriteCompatExt: tokio::io::AsyncWrite + Sized {
+    fn echo_compat_write(self) -> EchoCompat<Self> {
+        EchoCompat::new(self)
+    }
+}
+
+impl<T: tokio::io::AsyncWrite> TokioWriteCompatExt for T {}
+
+impl<T: AsyncRead + Unpin> tokio::io::AsyncRead for TokioCompat<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n =
+            ready!(Pin::new(&mut self.get_mut().inner).poll_read(cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> tokio::io::AsyncBufRead for TokioCompat<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.get_mut().inner).consume(amt)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> tokio::io::AsyncWrite for TokioCompat<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> AsyncRead for EchoCompat<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.get_mut().inner).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: tokio::io::AsyncBufRead + Unpin> AsyncBufRead for EchoCompat<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.get_mut().inner).consume(amt)
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> AsyncWrite for EchoCompat<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Shutdown, SocketAddr};
+
+    use tokio::io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _};
+
+    use super::*;
+    use crate::executor;
+    use crate::io::{self as echo_io, AsyncWriteExt, BufReader};
+    use crate::runtime::Builder;
+    use crate::tcp::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_tokio_traits_over_sim_tcp() {
+        let reply = Builder::simulation(5)
+            .run(async {
+                let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+                let addr = listener.local_addr()?;
+                let server = executor::spawn(async move {
+                    let (stream, _) = listener.accept().await?;
+                    let mut stream = BufReader::new(stream).tokio_compat();
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await? > 0 {
+                        stream.write_all(line.to_uppercase().as_bytes()).await?;
+                        line.clear();
+                    }
+                    stream.shutdown().await
+                });
+
+                let mut stream = TcpStream::connect(addr).await?.tokio_compat();
+                stream.write_all(b"hello\nworld\n").await?;
+                stream.get_mut().shutdown(Shutdown::Write)?;
+                let mut reply = String::new();
+                stream.read_to_string(&mut reply).await?;
+                server.await.unwrap()?;
+                io::Result::Ok(reply)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!("HELLO\nWORLD\n", reply);
+    }
+
+    #[tokio::test]
+    async fn test_echo_traits_over_tokio_duplex() {
+        let (a, b) = tokio::io::duplex(4);
+        let (mut a_read, mut a_write) = tokio::io::split(a);
+        let writer = tokio::spawn(async move {
+            a_write.write_all(b"through a small pipe").await?;
+            a_write.shutdown().await
+        });
+
+        let mut b = b.echo_compat();
+        let mut received = Vec::new();
+        echo_io::copy(&mut b, &mut received).await.unwrap();
+        writer.await.unwrap().unwrap();
+        assert_eq!(b"through a small pipe", &received[..]);
+
+        AsyncWriteExt::write_all(&mut b, b"back").await.unwrap();
+        AsyncWriteExt::shutdown(&mut b).await.unwrap();
+        let mut back = Vec::new();
+        a_read.read_to_end(&mut back).await.unwrap();
+        assert_eq!(b"back", &back[..]);
+    }
+
+    #[tokio::test]
+    async fn test_writers_wrap_both_ways() {
+        // `Vec<u8>` is a writer under both libraries, so the method picked
+        // decides which way it's adapted.
+        let mut tokio_side = Vec::new().tokio_compat_write();
+        tokio_side.write_all(b"to tokio").await.unwrap();
+        assert_eq!(b"to tokio", &tokio_side.into_inner()[..]);
+
+        let mut echo_side = Vec::new().echo_compat_write();
+        AsyncWriteExt::write_all(&mut echo_side, b"to echo")
+            .await
+            .unwrap();
+        assert_eq!(b"to echo", &echo_side.into_inner()[..]);
+    }
+}
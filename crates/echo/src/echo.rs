@@ -1,29 +1,52 @@
-type Reader = Box<dyn crate::io::AsyncRead>;
-type Writer = Box<dyn crate::io::AsyncWrite>;
+use crate::io::{self, AsyncRead, AsyncWrite};
 
-pub struct Echo {
-    buf: Vec<u8>,
-    r: Reader,
-    w: Writer,
+pub struct Echo<R, W> {
+    r: R,
+    w: W,
 }
 
-impl Echo {
-    fn new(buf_size: usize, read: Reader, write: Writer) -> Self {
-        Self {
-            buf: vec![0u8; buf_size],
-            r: read,
-            w: write,
-        }
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Echo<R, W> {
+    pub fn new(read: R, write: W) -> Self {
+        Self { r: read, w: write }
     }
 
-    async fn run(&mut self) -> Result<(), std::io::Error> {
-        loop {
-            let bytes_read = self.r.read(&mut self.buf).await?;
-            let mut bytes_written = 0;
-            while bytes_written < bytes_read {
-                let written = self.w.write(&self.buf[bytes_written..bytes_read]).await?;
-                bytes_written += written;
-            }
-        }
+    /// Copies everything from the reader to the writer, then shuts the writer
+    /// down once the reader reaches the end of the stream. Returns how many
+    /// bytes were echoed.
+    pub async fn run(&mut self) -> std::io::Result<u64> {
+        io::copy(&mut self.r, &mut self.w).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::runtime::Builder;
+
+    #[test]
+    fn test_echo_over_duplex() {
+        let (n, echoed) = Builder::simulation(0)
+            .run(async {
+                let (mut client_tx, server_rx) = io::duplex(8);
+                let (server_tx, mut client_rx) = io::duplex(8);
+                let server =
+                    executor::spawn(async move { Echo::new(server_rx, server_tx).run().await });
+
+                let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+                let writer = executor::spawn(async move {
+                    client_tx.write_all(&data).await?;
+                    client_tx.shutdown().await
+                });
+                let mut echoed = Vec::new();
+                client_rx.read_to_end(&mut echoed).await?;
+                writer.await.unwrap()?;
+                std::io::Result::Ok((server.await.unwrap()?, echoed))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(1000, n);
+        assert_eq!((0..1000u32).map(|i| i as u8).collect::<Vec<_>>(), echoed);
     }
 }
@@ -1,13 +1,22 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::os::fd::RawFd;
 use std::pin::Pin;
-use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::task::{Context, Poll, Wake};
+use std::thread::{self, ThreadId};
+use std::time::Instant;
 
-use crate::{EXECUTOR, channel};
+use crate::instrument::{self, Event};
+use crate::reactor::Notifier;
+use crate::{EXECUTOR, channel, sim};
 
-type TaskId = usize;
+mod blocking;
+
+pub use self::blocking::{BlockingHandle, spawn_blocking};
+
+pub type TaskId = usize;
 
 pub fn spawn<F, T>(fut: F) -> JoinHandle<T>
 where
@@ -21,13 +30,81 @@ pub fn make_progress() {
     local_executor(|e| e.run())
 }
 
+/// What a task was last waiting on when it returned `Pending`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Waiting {
+    /// A channel or synchronization primitive, named by its type.
+    Resource(&'static str),
+    /// A primitive that can be signalled from another thread, named by its
+    /// type. Never counts towards a deadlock, as the runtime can't see what
+    /// other threads are doing.
+    Remote(&'static str),
+    /// Readiness of a file descriptor registered with the reactor.
+    Io { fd: RawFd },
+    /// A timer.
+    Timer { deadline: Instant },
+    /// Another task's `JoinHandle`.
+    Task(TaskId),
+}
+
+impl Display for Waiting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Waiting::Resource(name) => write!(f, "{}", name),
+            Waiting::Remote(name) => write!(f, "{} (from another thread)", name),
+            Waiting::Io { fd } => write!(f, "IO on fd {}", fd),
+            Waiting::Timer { deadline } => write!(f, "timer due at {:?}", deadline),
+            Waiting::Task(id) => write!(f, "task {}", id),
+        }
+    }
+}
+
+/// Notes what the task currently being polled is about to wait on. Only
+/// recorded when the runtime is detecting deadlocks, and ignored outside of a
+/// runtime.
+pub(crate) fn record_wait(waiting: Waiting) {
+    EXECUTOR.with_borrow(|executor| {
+        if let Some(executor) = executor {
+            executor.record_wait(waiting);
+        }
+    })
+}
+
+/// Every task that has not completed, with what it was last waiting on if that
+/// was recorded.
+pub(crate) fn pending_tasks() -> Vec<(TaskId, Option<Waiting>)> {
+    local_executor(|e| {
+        let waits = e.waits.borrow();
+        let mut tasks: Vec<_> = e
+            .tasks
+            .borrow()
+            .keys()
+            .map(|id| (*id, waits.get(id).cloned()))
+            .collect();
+        tasks.sort_by_key(|(id, _)| *id);
+        tasks
+    })
+}
+
+/// Whether any task is waiting on something another thread could signal.
+pub(crate) fn has_remote_waits() -> bool {
+    local_executor(|e| {
+        e.waits
+            .borrow()
+            .values()
+            .any(|waiting| matches!(waiting, Waiting::Remote(_)))
+    })
+}
+
+// Only a shared borrow is taken here, as tasks being polled by `make_progress`
+// need to be able to spawn more tasks.
 fn local_executor<F, T>(f: F) -> T
 where
-    F: FnOnce(&mut Executor) -> T,
+    F: FnOnce(&Executor) -> T,
 {
-    EXECUTOR.with_borrow_mut(|executor| {
-        let executor = executor.as_mut().expect("Executor not initialized");
-        return f(executor);
+    EXECUTOR.with_borrow(|executor| {
+        let executor = executor.as_ref().expect("Executor not initialized");
+        f(executor)
     })
 }
 
@@ -37,9 +114,13 @@ pub struct Task {
 }
 
 pub struct Executor {
-    ready_tasks: Rc<RefCell<VecDeque<TaskId>>>,
-    tasks: HashMap<TaskId, Task>,
-    current_id: TaskId,
+    ready_tasks: Arc<ReadyQueue>,
+    tasks: RefCell<HashMap<TaskId, Task>>,
+    current_id: Cell<TaskId>,
+    /// The task being polled, if any.
+    current_task: Cell<Option<TaskId>>,
+    track_waits: bool,
+    waits: RefCell<HashMap<TaskId, Waiting>>,
 }
 
 pub struct JoinHandle<T> {
@@ -47,47 +128,86 @@ pub struct JoinHandle<T> {
     rx: channel::Receiver<T>,
 }
 
+impl<T> JoinHandle<T> {
+    /// Takes the task's output if it has completed.
+    pub(crate) fn try_take(&self) -> Option<T> {
+        self.rx.try_recv()
+    }
+}
+
 impl<T> Future for JoinHandle<T> {
     type Output = Result<T, channel::Error>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        Pin::new(&mut self.rx).poll(cx)
+        let poll = Pin::new(&mut self.rx).poll(cx);
+        if poll.is_pending() {
+            record_wait(Waiting::Task(self.task_id));
+        }
+        poll
     }
 }
 
 impl Executor {
     pub fn new() -> Self {
         Self {
-            ready_tasks: Rc::new(RefCell::new(VecDeque::new())),
-            tasks: HashMap::new(),
-            current_id: 0,
+            ready_tasks: Arc::new(ReadyQueue {
+                tasks: Mutex::new(VecDeque::new()),
+                thread: thread::current().id(),
+                notifier: OnceLock::new(),
+            }),
+            tasks: RefCell::new(HashMap::new()),
+            current_id: Cell::new(0),
+            current_task: Cell::new(None),
+            track_waits: false,
+            waits: RefCell::new(HashMap::new()),
         }
     }
 
-    pub fn spawn<F, T>(&mut self, fut: F) -> JoinHandle<T>
+    /// Records what each task waits on, for deadlock reports. Adds a small cost
+    /// to every `Pending`, so it is off by default.
+    pub fn track_waits(&mut self, enabled: bool) {
+        self.track_waits = enabled;
+    }
+
+    /// Lets wakes from other threads interrupt the reactor, which would
+    /// otherwise only notice them the next time it wakes up for its own IO.
+    pub(crate) fn set_notifier(&self, notifier: Arc<Notifier>) {
+        let _ = self.ready_tasks.notifier.set(notifier);
+    }
+
+    fn record_wait(&self, waiting: Waiting) {
+        if !self.track_waits {
+            return;
+        }
+        if let Some(id) = self.current_task.get() {
+            self.waits.borrow_mut().insert(id, waiting);
+        }
+    }
+
+    pub fn spawn<F, T>(&self, fut: F) -> JoinHandle<T>
     where
         F: Future<Output = T> + 'static,
         T: 'static,
     {
-        let id = self.current_id;
-        self.current_id += 1;
+        let id = self.current_id.get();
+        self.current_id.set(id + 1);
 
         let (tx, rx) = channel::oneshot::<T>();
-        println!("Creating task for id: {}", id);
         let task = Task {
             id,
             future: Box::pin(async move {
-                println!("Running task for id: {}", id);
-                tx.send(fut.await).unwrap();
+                // Nobody may be waiting on the result any more.
+                let _ = tx.send(fut.await);
             }),
         };
-        self.tasks.insert(id, task);
-        self.ready_tasks.borrow_mut().push_back(id);
+        self.tasks.borrow_mut().insert(id, task);
+        self.ready_tasks.push(id);
+        instrument::emit(Event::Spawn { task: id });
 
         JoinHandle { rx, task_id: id }
     }
 
-    fn block_on<F, T>(&mut self, fut: F) -> Result<T, String>
+    fn block_on<F, T>(&self, fut: F) -> Result<T, String>
     where
         F: Future<Output = T> + 'static,
         T: 'static,
@@ -96,7 +216,7 @@ impl Executor {
         let id = handle.task_id;
         loop {
             self.run();
-            if self.tasks.get(&id).is_none() {
+            if self.tasks.borrow().get(&id).is_none() {
                 match handle.rx.try_recv() {
                     Some(value) => return Ok(value),
                     None => return Err(format!("Future did not resolve to a value")),
@@ -105,19 +225,23 @@ impl Executor {
         }
     }
 
-    fn run(&mut self) {
+    fn run(&self) {
         loop {
             // We cannot hold a borrow to the ready tasks while also polling,
             // as there is a risk that the poll will cause a wake that modifies
             // the ready tasks. So we pop the task ID first, drop the borrow,
             // and then poll.
-            let task_id = match self.ready_tasks.borrow_mut().pop_front() {
+            let task_id = match Self::next_ready(&mut self.ready_tasks.lock()) {
                 Some(task_id) => task_id,
                 None => break,
             };
 
-            let Some(task) = self.tasks.get_mut(&task_id) else {
-                panic!("Task in ready set, but not tracked in map.");
+            // A task can be woken more than once before it is polled, or after
+            // it has already completed, so stale IDs are expected here. The task
+            // is taken out of the map while it is polled so that it can spawn
+            // new tasks.
+            let Some(mut task) = self.tasks.borrow_mut().remove(&task_id) else {
+                continue;
             };
 
             let waker = Arc::new(Waker {
@@ -127,39 +251,76 @@ impl Executor {
             .into();
 
             let mut context = Context::from_waker(&waker);
-            match task.future.as_mut().poll(&mut context) {
-                Poll::Ready(()) => {
-                    self.tasks.remove(&task_id);
-                }
-                Poll::Pending => {}
+            instrument::emit(Event::PollStart { task: task_id });
+            self.current_task.set(Some(task_id));
+            let poll = task.future.as_mut().poll(&mut context);
+            self.current_task.set(None);
+            instrument::emit(Event::PollEnd {
+                task: task_id,
+                ready: poll.is_ready(),
+            });
+
+            if poll.is_ready() {
+                self.waits.borrow_mut().remove(&task_id);
+                drop(task);
+                instrument::emit(Event::TaskDrop { task: task_id });
+            } else {
+                self.tasks.borrow_mut().insert(task_id, task);
             }
         }
     }
+
+    /// Pops the next task to poll. Tasks run in FIFO order, except under a
+    /// simulation where the order is drawn from the simulation's seed.
+    fn next_ready(ready: &mut VecDeque<TaskId>) -> Option<TaskId> {
+        if ready.is_empty() {
+            return None;
+        }
+
+        let index = sim::choose(ready.len()).unwrap_or(0);
+        ready.remove(index)
+    }
+}
+
+/// Tasks that have been woken and are waiting to be polled. Wakers may be
+/// sent to other threads, for example by the channels in `channel::mt`, so the
+/// queue is behind a `Mutex` rather than a `RefCell`.
+struct ReadyQueue {
+    tasks: Mutex<VecDeque<TaskId>>,
+    /// The thread the executor runs on.
+    thread: ThreadId,
+    notifier: OnceLock<Arc<Notifier>>,
+}
+
+impl ReadyQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<TaskId>> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, task_id: TaskId) {
+        self.lock().push_back(task_id);
+        if thread::current().id() != self.thread
+            && let Some(notifier) = self.notifier.get()
+        {
+            notifier.notify();
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Waker {
     task_id: TaskId,
-    ready_tasks: Rc<RefCell<VecDeque<TaskId>>>,
+    ready_tasks: Arc<ReadyQueue>,
 }
 
-// We're never going to send a Waker across threads, so this is safe.
-unsafe impl Send for Waker {}
-unsafe impl Sync for Waker {}
-
 impl Wake for Waker {
     fn wake(self: Arc<Self>) {
-        println!("Waking task {}", self.task_id);
-        let mut ready = self.ready_tasks.borrow_mut();
-        ready.push_back(self.task_id);
+        self.wake_by_ref();
     }
-}
 
-impl Clone for Waker {
-    fn clone(&self) -> Self {
-        Self {
-            task_id: self.task_id,
-            ready_tasks: Rc::clone(&self.ready_tasks),
-        }
+    fn wake_by_ref(self: &Arc<Self>) {
+        instrument::emit(Event::Wake { task: self.task_id });
+        self.ready_tasks.push(self.task_id);
     }
 }
 
@@ -169,13 +330,13 @@ mod test {
 
     #[test]
     fn test_spawn() {
-        let mut executor = Executor::new();
+        let executor = Executor::new();
         executor.spawn(async { 1 + 2 });
     }
 
     #[test]
     fn test_block_on() {
-        let mut executor = Executor::new();
+        let executor = Executor::new();
         let (tx, rx) = channel::oneshot::<i32>();
         tx.send(42).unwrap();
         let result = executor.block_on(async move {
@@ -189,7 +350,7 @@ mod test {
 
     #[test]
     fn test_send_from_fut() {
-        let mut executor = Executor::new();
+        let executor = Executor::new();
         let (tx, rx) = channel::oneshot::<i32>();
         executor.spawn(async move {
             println!("Sending 42");
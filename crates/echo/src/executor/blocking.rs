@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use crate::SIMULATION;
+use crate::channel::{self, mt};
+
+/// The most threads the pool will run at once. Work beyond that queues.
+const MAX_THREADS: usize = 64;
+
+/// How long an idle thread waits for more work before exiting.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs `f` on a pool of threads shared by every runtime in the process, so
+/// that blocking calls like file IO don't hold up other tasks. The handle
+/// fails with `ChannelClosed` if `f` panics.
+///
+/// Under a simulation `f` runs straight away on the current thread, as the
+/// simulation can't see what other threads do.
+pub fn spawn_blocking<F, T>(f: F) -> BlockingHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = mt::oneshot();
+    let job = move || {
+        if let Ok(output) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            let _ = tx.send(output);
+        }
+    };
+
+    if SIMULATION.with_borrow(|sim| sim.is_some()) {
+        job();
+    } else {
+        pool().submit(Box::new(job));
+    }
+    BlockingHandle { rx }
+}
+
+/// The output of a closure passed to [`spawn_blocking`].
+pub struct BlockingHandle<T> {
+    rx: mt::Receiver<T>,
+}
+
+impl<T> Future for BlockingHandle<T> {
+    type Output = Result<T, channel::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx).poll(cx)
+    }
+}
+
+fn pool() -> &'static Pool {
+    static POOL: OnceLock<Pool> = OnceLock::new();
+    POOL.get_or_init(|| Pool {
+        state: Mutex::new(State::default()),
+        work: Condvar::new(),
+    })
+}
+
+struct Pool {
+    state: Mutex<State>,
+    work: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+}
+
+impl Pool {
+    fn submit(&'static self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back(job);
+        // Idle threads that have been notified but not woken yet still count
+        // as idle, so compare against everything queued.
+        if state.idle >= state.jobs.len() {
+            self.work.notify_one();
+            return;
+        }
+        if state.threads < MAX_THREADS {
+            state.threads += 1;
+            thread::Builder::new()
+                .name("echo-blocking".into())
+                .spawn(move || self.run_worker())
+                .expect("failed to spawn a blocking pool thread");
+        }
+    }
+
+    fn run_worker(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state.idle += 1;
+            let (next, timeout) = self.work.wait_timeout(state, KEEP_ALIVE).unwrap();
+            state = next;
+            state.idle -= 1;
+            if timeout.timed_out() && state.jobs.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::runtime::{self, Builder};
+
+    #[test]
+    fn test_runs_off_the_runtime_thread() {
+        let (runtime_thread, worker_thread) = runtime::run(async {
+            let worker = spawn_blocking(|| {
+                thread::sleep(Duration::from_millis(10));
+                thread::current().id()
+            });
+            (thread::current().id(), worker.await.unwrap())
+        })
+        .unwrap();
+        assert_ne!(runtime_thread, worker_thread);
+    }
+
+    #[test]
+    fn test_panics_fail_the_handle() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        runtime::run({
+            let ran = ran.clone();
+            async move {
+                let panicked = spawn_blocking(|| panic!("expected in a test")).await;
+                assert!(panicked.is_err());
+
+                let handles: Vec<_> = (0..4)
+                    .map(|_| {
+                        let ran = ran.clone();
+                        spawn_blocking(move || ran.fetch_add(1, Ordering::SeqCst))
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            }
+        })
+        .unwrap();
+        assert_eq!(4, ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_inline_under_simulation() {
+        let same_thread = Builder::simulation(0)
+            .run(async {
+                let id = spawn_blocking(|| thread::current().id()).await.unwrap();
+                id == thread::current().id()
+            })
+            .unwrap();
+        assert!(same_thread);
+    }
+}
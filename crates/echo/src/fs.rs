@@ -0,0 +1,456 @@
+//! Filesystem access that doesn't stall the runtime.
+//!
+//! Every call runs on the blocking pool from [`executor::spawn_blocking`], so
+//! under a simulation they run inline, and real files are touched either way.
+
+use std::collections::VecDeque;
+use std::fs::{self as std_fs, DirEntry, Metadata};
+use std::future::poll_fn;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use crate::executor::{self, BlockingHandle};
+use crate::io::blocking::Blocking;
+use crate::io::{AsyncRead, AsyncWrite};
+use crate::stream::Stream;
+
+/// How many directory entries [`ReadDir`] reads per trip to the blocking pool.
+const DIR_CHUNK: usize = 32;
+
+async fn asyncify<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    executor::spawn_blocking(f)
+        .await
+        .map_err(|_| io::Error::other("filesystem call panicked on the blocking pool"))?
+}
+
+/// Reads a whole file.
+pub async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref().to_owned();
+    asyncify(move || std_fs::read(path)).await
+}
+
+/// Writes a whole file, creating it or replacing what was there.
+pub async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    let contents = contents.as_ref().to_owned();
+    asyncify(move || std_fs::write(path, contents)).await
+}
+
+pub async fn metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    let path = path.as_ref().to_owned();
+    asyncify(move || std_fs::metadata(path)).await
+}
+
+/// Creates a directory and any missing parents.
+pub async fn create_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    asyncify(move || std_fs::create_dir_all(path)).await
+}
+
+/// Lists a directory's entries, in no particular order.
+pub async fn read_dir(path: impl AsRef<Path>) -> io::Result<ReadDir> {
+    let path = path.as_ref().to_owned();
+    let dir = asyncify(move || std_fs::read_dir(path)).await?;
+    Ok(ReadDir {
+        state: DirState::Idle(Some(DirChunk {
+            entries: VecDeque::new(),
+            dir,
+            done: false,
+        })),
+    })
+}
+
+/// The entries of a directory, from [`read_dir`]. Also a [`Stream`].
+///
+/// Entries are `std::fs::DirEntry`s. Their `path` and `file_name` are free,
+/// but `metadata` and `file_type` can block.
+pub struct ReadDir {
+    state: DirState,
+}
+
+struct DirChunk {
+    entries: VecDeque<io::Result<DirEntry>>,
+    dir: std_fs::ReadDir,
+    /// Whether `dir` has run out.
+    done: bool,
+}
+
+enum DirState {
+    Idle(Option<DirChunk>),
+    Busy(BlockingHandle<DirChunk>),
+}
+
+impl ReadDir {
+    /// Returns the next entry, or `None` once every entry has been returned.
+    pub async fn next_entry(&mut self) -> io::Result<Option<DirEntry>> {
+        poll_fn(|cx| self.poll_next_entry(cx)).await
+    }
+
+    pub fn poll_next_entry(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<DirEntry>>> {
+        loop {
+            match &mut self.state {
+                DirState::Idle(chunk) => {
+                    let chunk_ref = chunk.as_mut().unwrap();
+                    if let Some(entry) = chunk_ref.entries.pop_front() {
+                        return Poll::Ready(entry.map(Some));
+                    }
+                    if chunk_ref.done {
+                        return Poll::Ready(Ok(None));
+                    }
+
+                    let mut chunk = chunk.take().unwrap();
+                    self.state = DirState::Busy(executor::spawn_blocking(move || {
+                        chunk.entries.extend(chunk.dir.by_ref().take(DIR_CHUNK));
+                        chunk.done = chunk.entries.len() < DIR_CHUNK;
+                        chunk
+                    }));
+                }
+                DirState::Busy(handle) => {
+                    let chunk = ready!(Pin::new(handle).poll(cx)).map_err(|_| {
+                        io::Error::other("reading a directory panicked on the blocking pool")
+                    })?;
+                    self.state = DirState::Idle(Some(chunk));
+                }
+            }
+        }
+    }
+}
+
+impl Stream for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_entry(cx).map(Result::transpose)
+    }
+}
+
+/// An open file. Reads and writes go through the blocking pool one at a time,
+/// and a write returns once its data has been copied, so errors writing it
+/// show up on a later call. Flushing waits for everything written to reach the
+/// OS, and [`File::sync_all`] for it to reach the disk.
+pub struct File {
+    std: Arc<std_fs::File>,
+    inner: Blocking<SharedFile>,
+}
+
+impl File {
+    /// Opens a file for reading.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        asyncify(move || std_fs::File::open(path))
+            .await
+            .map(Self::from_std)
+    }
+
+    /// Opens a file for writing, creating it or truncating what was there.
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        asyncify(move || std_fs::File::create(path))
+            .await
+            .map(Self::from_std)
+    }
+
+    pub fn from_std(file: std_fs::File) -> Self {
+        let std = Arc::new(file);
+        Self {
+            inner: Blocking::new(SharedFile(std.clone())),
+            std,
+        }
+    }
+
+    /// Waits for any write in flight, then hands back the std file.
+    pub async fn into_std(mut self) -> io::Result<std_fs::File> {
+        self.idle().await?;
+        drop(self.inner);
+        Ok(Arc::into_inner(self.std).expect("no blocking calls left using the file"))
+    }
+
+    /// Moves the file's position, returning the new one measured from the
+    /// start.
+    pub async fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.idle().await?;
+        let std = self.std.clone();
+        asyncify(move || (&*std).seek(pos)).await
+    }
+
+    pub async fn metadata(&mut self) -> io::Result<Metadata> {
+        self.idle().await?;
+        let std = self.std.clone();
+        asyncify(move || std.metadata()).await
+    }
+
+    /// Truncates or extends the file to `size` bytes.
+    pub async fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.idle().await?;
+        let std = self.std.clone();
+        asyncify(move || std.set_len(size)).await
+    }
+
+    /// Waits for everything written so far, and the file's metadata, to reach
+    /// the disk.
+    pub async fn sync_all(&mut self) -> io::Result<()> {
+        self.idle().await?;
+        let std = self.std.clone();
+        asyncify(move || std.sync_all()).await
+    }
+
+    /// Like [`File::sync_all`], but only waits for the metadata needed to read
+    /// the data back.
+    pub async fn sync_data(&mut self) -> io::Result<()> {
+        self.idle().await?;
+        let std = self.std.clone();
+        asyncify(move || std.sync_data()).await
+    }
+
+    /// Waits out any call in flight, and puts the file's position back to
+    /// where the caller thinks it is.
+    async fn idle(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_idle(cx)).await
+    }
+
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut buf = ready!(self.inner.poll_complete(cx))?;
+        let unread = buf.remaining();
+        // Only moves the position, so doesn't block. Should it fail, the data
+        // read ahead is kept, so that the position still matches it.
+        if unread > 0
+            && let Err(e) = (&*self.std).seek(SeekFrom::Current(-(unread as i64)))
+        {
+            self.inner.restore(buf);
+            return Poll::Ready(Err(e));
+        }
+        buf.clear();
+        self.inner.restore(buf);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_idle(cx))?;
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    /// Files have no write side to close, so this only flushes.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Lets the blocking pool use the file while [`File`] keeps a handle to it for
+/// seeking and metadata.
+struct SharedFile(Arc<std_fs::File>);
+
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+}
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::runtime::{self, Builder};
+    use crate::stream::StreamExt;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("echo-fs-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_whole_file_helpers() {
+        let dir = temp_dir("helpers");
+        let names = runtime::run({
+            let dir = dir.clone();
+            async move {
+                create_dir_all(dir.join("nested/deeper")).await?;
+                write(dir.join("a.txt"), b"alpha").await?;
+                write(dir.join("nested/b.txt"), b"beta").await?;
+                assert_eq!(b"alpha", &read(dir.join("a.txt")).await?[..]);
+                assert_eq!(4, metadata(dir.join("nested/b.txt")).await?.len());
+                assert!(metadata(dir.join("nested")).await?.is_dir());
+
+                let mut names = Vec::new();
+                let mut entries = read_dir(&dir).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    names.push(entry.file_name().into_string().unwrap());
+                }
+                names.sort();
+                io::Result::Ok(names)
+            }
+        })
+        .unwrap();
+        std_fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vec!["a.txt", "nested"], names.unwrap());
+    }
+
+    #[test]
+    fn test_read_dir_in_chunks_as_a_stream() {
+        let dir = temp_dir("many");
+        std_fs::create_dir_all(&dir).unwrap();
+        for i in 0..DIR_CHUNK * 2 + 5 {
+            std_fs::write(dir.join(i.to_string()), b"").unwrap();
+        }
+
+        let count = runtime::run({
+            let dir = dir.clone();
+            async move {
+                let mut entries = read_dir(dir).await?;
+                let mut count = 0;
+                while let Some(entry) = entries.next().await {
+                    entry?;
+                    count += 1;
+                }
+                io::Result::Ok(count)
+            }
+        })
+        .unwrap();
+        std_fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(DIR_CHUNK * 2 + 5, count.unwrap());
+    }
+
+    #[test]
+    fn test_file_read_write_and_seek() {
+        let dir = temp_dir("file");
+        std_fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        let contents = runtime::run({
+            let path = path.clone();
+            async move {
+                let mut file = File::create(&path).await?;
+                file.write_all(b"hello world").await?;
+                file.flush().await?;
+                assert_eq!(11, file.metadata().await?.len());
+                drop(file);
+
+                let mut file = File::from_std(
+                    std_fs::OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .open(&path)?,
+                );
+                // Start a large read and abandon it while it's in flight, so
+                // that the next read leaves data read ahead. That mustn't move
+                // where the next write lands. The pool can finish the read
+                // before it's polled, so go again until it doesn't.
+                let mut abandoned = [0; 64];
+                while poll_fn(|cx| {
+                    Poll::Ready(Pin::new(&mut file).poll_read(cx, &mut abandoned).is_ready())
+                })
+                .await
+                {
+                    file.seek(SeekFrom::Start(0)).await?;
+                }
+                let mut hello = [0; 5];
+                file.read_exact(&mut hello).await?;
+                assert_eq!(b"hello", &hello);
+                file.write_all(b", there").await?;
+                file.flush().await?;
+
+                assert_eq!(0, file.seek(SeekFrom::Start(0)).await?);
+                let mut contents = String::new();
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf).await?;
+                contents.push_str(std::str::from_utf8(&buf).unwrap());
+                file.set_len(5).await?;
+                file.sync_all().await?;
+                assert_eq!(5, file.into_std().await?.metadata()?.len());
+                io::Result::Ok(contents)
+            }
+        })
+        .unwrap();
+        std_fs::remove_dir_all(&dir).unwrap();
+        assert_eq!("hello, there", contents.unwrap());
+    }
+
+    #[test]
+    fn test_write_after_abandoned_read() {
+        let dir = temp_dir("abandoned");
+        std_fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data");
+        std_fs::write(&path, b"hello world").unwrap();
+        let std = std_fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        runtime::run(async move {
+            let mut file = File::from_std(std);
+            // The read ahead is never handed out, so the write belongs at the
+            // start. Go again if the pool finished the read before it's polled.
+            let mut abandoned = [0; 64];
+            while poll_fn(|cx| {
+                Poll::Ready(Pin::new(&mut file).poll_read(cx, &mut abandoned).is_ready())
+            })
+            .await
+            {
+                file.seek(SeekFrom::Start(0)).await?;
+            }
+            file.write_all(b"HELLO").await?;
+            file.flush().await
+        })
+        .unwrap()
+        .unwrap();
+        let contents = std_fs::read(&path).unwrap();
+        std_fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(b"HELLO world", &contents[..]);
+    }
+
+    #[test]
+    fn test_works_inline_under_simulation() {
+        let dir = temp_dir("sim");
+        let read_back = Builder::simulation(0)
+            .run({
+                let dir = dir.clone();
+                async move {
+                    create_dir_all(&dir).await?;
+                    let mut file = File::create(dir.join("log")).await?;
+                    file.write_all(b"line\n").await?;
+                    file.shutdown().await?;
+                    read(dir.join("log")).await
+                }
+            })
+            .unwrap();
+        std_fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(b"line\n", &read_back.unwrap()[..]);
+    }
+}
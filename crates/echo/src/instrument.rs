@@ -0,0 +1,221 @@
+//! Hooks for observing the runtime.
+//!
+//! The executor, reactor and channels report what they are doing as [`Event`]s.
+//! Nothing is emitted unless the `instrument` feature is enabled, in which case
+//! events are handed to the [`Subscriber`] installed on the current thread. With
+//! the `log` feature, [`LogSubscriber`] forwards them to the `log` facade.
+
+use std::fmt::Display;
+use std::os::fd::RawFd;
+
+use crate::executor::TaskId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A task was added to the executor.
+    Spawn { task: TaskId },
+    /// The executor is about to poll a task.
+    PollStart { task: TaskId },
+    /// The executor finished polling a task.
+    PollEnd { task: TaskId, ready: bool },
+    /// A task was moved back onto the ready queue.
+    Wake { task: TaskId },
+    /// A task completed and was removed from the executor.
+    TaskDrop { task: TaskId },
+    /// A channel receiver was dropped.
+    ReceiverDrop,
+    /// The reactor is about to block waiting for events.
+    ReactorWait { timeout: i32 },
+    /// The reactor received readiness for a file descriptor.
+    ReactorEvent { fd: RawFd, events: u32 },
+}
+
+impl Event {
+    /// The module the event originates from, useful for filtering.
+    pub fn target(&self) -> &'static str {
+        match self {
+            Event::Spawn { .. }
+            | Event::PollStart { .. }
+            | Event::PollEnd { .. }
+            | Event::Wake { .. }
+            | Event::TaskDrop { .. } => "echo::executor",
+            Event::ReceiverDrop => "echo::channel",
+            Event::ReactorWait { .. } | Event::ReactorEvent { .. } => "echo::reactor",
+        }
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Spawn { task } => write!(f, "spawned task {}", task),
+            Event::PollStart { task } => write!(f, "polling task {}", task),
+            Event::PollEnd { task, ready } => {
+                write!(f, "polled task {} (ready: {})", task, ready)
+            }
+            Event::Wake { task } => write!(f, "waking task {}", task),
+            Event::TaskDrop { task } => write!(f, "dropped task {}", task),
+            Event::ReceiverDrop => write!(f, "dropped receiver"),
+            Event::ReactorWait { timeout } => {
+                write!(f, "waiting for events (timeout: {})", timeout)
+            }
+            Event::ReactorEvent { fd, events } => {
+                write!(f, "fd {} ready (events: {:#x})", fd, events)
+            }
+        }
+    }
+}
+
+pub trait Subscriber {
+    fn event(&self, event: &Event);
+}
+
+#[cfg(feature = "instrument")]
+thread_local! {
+    static SUBSCRIBER: std::cell::RefCell<Option<Box<dyn Subscriber>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Installs a subscriber for events emitted on the current thread, replacing any
+/// previous one.
+#[cfg(feature = "instrument")]
+pub fn set_subscriber<S: Subscriber + 'static>(subscriber: S) {
+    SUBSCRIBER.with_borrow_mut(|s| *s = Some(Box::new(subscriber)));
+}
+
+/// Removes the subscriber installed on the current thread, if any.
+#[cfg(feature = "instrument")]
+pub fn clear_subscriber() {
+    SUBSCRIBER.with_borrow_mut(|s| *s = None);
+}
+
+#[inline(always)]
+pub(crate) fn emit(event: Event) {
+    #[cfg(feature = "instrument")]
+    SUBSCRIBER.with_borrow(|s| {
+        if let Some(subscriber) = s {
+            subscriber.event(&event);
+        }
+    });
+
+    #[cfg(not(feature = "instrument"))]
+    let _ = event;
+}
+
+/// Forwards events to the `log` facade at trace level, using [`Event::target`]
+/// as the log target.
+#[cfg(feature = "log")]
+pub struct LogSubscriber;
+
+#[cfg(feature = "log")]
+impl Subscriber for LogSubscriber {
+    fn event(&self, event: &Event) {
+        log::trace!(target: event.target(), "{}", event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor::{self, Executor};
+
+    /// Runs a task to completion, emitting the executor's events.
+    fn run_one_task() {
+        crate::EXECUTOR.set(Some(Executor::new()));
+        let handle = executor::spawn(async { 42 });
+        executor::make_progress();
+        assert_eq!(Some(42), handle.try_take());
+        drop(handle);
+        crate::EXECUTOR.set(None);
+    }
+
+    #[test]
+    fn test_display_and_target() {
+        let event = Event::PollEnd {
+            task: 3,
+            ready: false,
+        };
+        assert_eq!("polled task 3 (ready: false)", event.to_string());
+        assert_eq!("echo::executor", event.target());
+
+        let event = Event::ReactorEvent { fd: 7, events: 5 };
+        assert_eq!("fd 7 ready (events: 0x5)", event.to_string());
+        assert_eq!("echo::reactor", event.target());
+        assert_eq!("echo::channel", Event::ReceiverDrop.target());
+    }
+
+    /// Without the `instrument` feature there is nowhere for events to go,
+    /// and emitting them must not get in the way of the runtime.
+    #[test]
+    fn test_emitting_without_a_subscriber() {
+        emit(Event::ReactorWait { timeout: -1 });
+        run_one_task();
+    }
+
+    #[cfg(feature = "instrument")]
+    #[test]
+    fn test_task_lifecycle_events() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct Recorder(Rc<RefCell<Vec<Event>>>);
+
+        impl Subscriber for Recorder {
+            fn event(&self, event: &Event) {
+                self.0.borrow_mut().push(*event);
+            }
+        }
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        set_subscriber(Recorder(events.clone()));
+        run_one_task();
+        clear_subscriber();
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                Event::Spawn { task: 0 },
+                Event::PollStart { task: 0 },
+                Event::PollEnd {
+                    task: 0,
+                    ready: true
+                },
+                Event::TaskDrop { task: 0 },
+                Event::ReceiverDrop,
+            ]
+        );
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_subscriber() {
+        use std::sync::Mutex;
+
+        static RECORDS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+        struct Logger;
+
+        impl log::Log for Logger {
+            fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record<'_>) {
+                let record = (record.target().to_string(), record.args().to_string());
+                RECORDS.lock().unwrap().push(record);
+            }
+
+            fn flush(&self) {}
+        }
+
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        set_subscriber(LogSubscriber);
+        run_one_task();
+        clear_subscriber();
+
+        let records = RECORDS.lock().unwrap();
+        assert!(records.contains(&("echo::executor".into(), "spawned task 0".into())));
+        assert!(records.contains(&("echo::channel".into(), "dropped receiver".into())));
+    }
+}
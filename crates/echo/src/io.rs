@@ -1,15 +1,422 @@
-use std::future::Future;
-use std::io;
+use std::future::{Future, poll_fn};
+use std::io::{self, IoSlice, IoSliceMut};
 use std::pin::Pin;
+use std::task::{Context, Poll};
 
-type IoFuture = Pin<Box<dyn Future<Output = Result<usize, io::Error>>>>;
+mod async_fd;
+pub(crate) mod blocking;
+mod buf;
+mod copy;
+mod duplex;
+mod splice;
+mod stdio;
 
+pub use self::async_fd::{AsyncFd, AsyncFdReadyGuard, TryIoError};
+pub use self::buf::{AsyncBufRead, AsyncBufReadExt, BufReader, BufWriter, FillBuf, Lines};
+pub use self::copy::{copy, copy_bidirectional};
+pub use self::duplex::{DuplexStream, duplex};
+pub use self::splice::splice_copy;
+pub use self::stdio::{Stderr, Stdin, Stdout, stderr, stdin, stdout};
+
+/// A source of bytes that can be read from without blocking the runtime.
 pub trait AsyncRead {
     /// Reads data into the provided buffer, returning the number of bytes read.
-    fn read(&mut self, buf: &mut [u8]) -> IoFuture;
+    /// Zero means the end of the stream, unless `buf` was empty. Registers the
+    /// task to be woken and returns `Pending` when no data is available yet.
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>;
+
+    /// Like [`AsyncRead::poll_read`], but fills `bufs` in order. By default
+    /// only the first non-empty buffer is read into.
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let buf = bufs
+            .iter_mut()
+            .find(|b| !b.is_empty())
+            .map_or(&mut [][..], |b| &mut **b);
+        self.poll_read(cx, buf)
+    }
 }
 
+/// A sink for bytes that can be written to without blocking the runtime.
 pub trait AsyncWrite {
+    /// Writes data from the provided buffer, returning the number of bytes
+    /// written. Registers the task to be woken and returns `Pending` when no
+    /// room is available yet.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+
+    /// Like [`AsyncWrite::poll_write`], but takes the data from `bufs` in
+    /// order. By default only the first non-empty buffer is written; check
+    /// [`AsyncWrite::is_write_vectored`] before relying on anything more.
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let buf = bufs
+            .iter()
+            .find(|b| !b.is_empty())
+            .map_or(&[][..], |b| &**b);
+        self.poll_write(cx, buf)
+    }
+
+    /// Whether [`AsyncWrite::poll_write_vectored`] writes from more than one
+    /// buffer at a time, so callers know it's worth gathering writes.
+    fn is_write_vectored(&self) -> bool {
+        false
+    }
+
+    /// Pushes out anything buffered along the way.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Flushes and then closes the write side, so the reader sees the end of
+    /// the stream.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for &mut T {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read_vectored(cx, bufs)
+    }
+}
+
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for Box<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read_vectored(cx, bufs)
+    }
+}
+
+/// Reads from the front of the slice, which shrinks as it is read.
+impl AsyncRead for &[u8] {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read(&mut *self, buf))
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read_vectored(&mut *self, bufs))
+    }
+}
+
+impl<T: AsyncWrite + Unpin + ?Sized> AsyncWrite for &mut T {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        (**self).is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_shutdown(cx)
+    }
+}
+
+impl<T: AsyncWrite + Unpin + ?Sized> AsyncWrite for Box<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        (**self).is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_shutdown(cx)
+    }
+}
+
+/// Appends everything written, and never waits.
+impl AsyncWrite for Vec<u8> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Write::write_vectored(self.get_mut(), bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Async helpers for every [`AsyncRead`].
+pub trait AsyncReadExt: AsyncRead {
+    /// Reads data into the provided buffer, returning the number of bytes read.
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_read(cx, buf))
+    }
+
+    /// Reads data into `bufs` in order, returning the number of bytes read.
+    fn read_vectored<'a>(
+        &'a mut self,
+        bufs: &'a mut [IoSliceMut<'_>],
+    ) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_read_vectored(cx, bufs))
+    }
+
+    /// Fills the whole buffer, failing with `UnexpectedEof` if the stream ends
+    /// first.
+    fn read_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> impl Future<Output = io::Result<()>> + 'a
+    where
+        Self: Unpin,
+    {
+        let mut filled = 0;
+        poll_fn(move |cx| {
+            while filled < buf.len() {
+                match std::task::ready!(Pin::new(&mut *self).poll_read(cx, &mut buf[filled..])) {
+                    Ok(0) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+            Poll::Ready(Ok(()))
+        })
+    }
+
+    /// Reads until the end of the stream, appending to `buf`. Returns how many
+    /// bytes were read.
+    fn read_to_end<'a>(
+        &'a mut self,
+        buf: &'a mut Vec<u8>,
+    ) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        let start = buf.len();
+        poll_fn(move |cx| {
+            let mut chunk = [0u8; 4096];
+            loop {
+                match std::task::ready!(Pin::new(&mut *self).poll_read(cx, &mut chunk)) {
+                    Ok(0) => return Poll::Ready(Ok(buf.len() - start)),
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+        })
+    }
+}
+
+impl<R: AsyncRead + ?Sized> AsyncReadExt for R {}
+
+/// Async helpers for every [`AsyncWrite`].
+pub trait AsyncWriteExt: AsyncWrite {
     /// Writes data from the provided buffer, returning the number of bytes written.
-    fn write(&mut self, buf: &[u8]) -> IoFuture;
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_write(cx, buf))
+    }
+
+    /// Writes data from `bufs` in order, returning the number of bytes written.
+    fn write_vectored<'a>(
+        &'a mut self,
+        bufs: &'a [IoSlice<'_>],
+    ) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_write_vectored(cx, bufs))
+    }
+
+    /// Writes the whole buffer, failing with `WriteZero` if the writer stops
+    /// accepting data first.
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = io::Result<()>> + 'a
+    where
+        Self: Unpin,
+    {
+        let mut written = 0;
+        poll_fn(move |cx| {
+            while written < buf.len() {
+                match std::task::ready!(Pin::new(&mut *self).poll_write(cx, &buf[written..])) {
+                    Ok(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                    Ok(n) => written += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+            Poll::Ready(Ok(()))
+        })
+    }
+
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + '_
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_flush(cx))
+    }
+
+    fn shutdown(&mut self) -> impl Future<Output = io::Result<()>> + '_
+    where
+        Self: Unpin,
+    {
+        poll_fn(move |cx| Pin::new(&mut *self).poll_shutdown(cx))
+    }
+}
+
+impl<W: AsyncWrite + ?Sized> AsyncWriteExt for W {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_exact() {
+        let mut src: &[u8] = b"hello world";
+        let mut buf = [0u8; 5];
+        src.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"hello", &buf);
+        assert_eq!(b" world", src);
+
+        let mut buf = [0u8; 10];
+        let err = src.read_exact(&mut buf).await.unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[tokio::test]
+    async fn test_read_to_end() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let mut src = &data[..];
+        let mut out = b"prefix".to_vec();
+        assert_eq!(10_000, src.read_to_end(&mut out).await.unwrap());
+        assert_eq!(b"prefix", &out[..6]);
+        assert_eq!(data, out[6..]);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::needless_borrow)]
+    async fn test_write_all() {
+        let mut out = Vec::new();
+        out.write_all(b"hello").await.unwrap();
+        (&mut out).write_all(b" world").await.unwrap();
+        out.flush().await.unwrap();
+        assert_eq!(b"hello world", &out[..]);
+    }
+
+    #[tokio::test]
+    async fn test_vectored() {
+        let mut out = Vec::new();
+        let bufs = [
+            IoSlice::new(b"head"),
+            IoSlice::new(b""),
+            IoSlice::new(b"body"),
+        ];
+        assert!(out.is_write_vectored());
+        assert_eq!(8, out.write_vectored(&bufs).await.unwrap());
+
+        let mut src = &out[..];
+        let (mut head, mut body) = ([0u8; 4], [0u8; 8]);
+        let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body)];
+        assert_eq!(8, src.read_vectored(&mut bufs).await.unwrap());
+        assert_eq!(b"head", &head);
+        assert_eq!(b"body", &body[..4]);
+    }
+
+    #[tokio::test]
+    async fn test_dyn_reader() {
+        let mut src: Box<dyn AsyncRead + Unpin> = Box::new(&b"abc"[..]);
+        let mut buf = [0u8; 8];
+        assert_eq!(3, src.read(&mut buf).await.unwrap());
+        assert_eq!(0, src.read(&mut buf).await.unwrap());
+    }
 }
@@ -0,0 +1,260 @@
+use std::cell::Cell;
+use std::fmt;
+use std::future::poll_fn;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::task::Poll;
+
+use crate::reactor::{self, Direction};
+
+/// Registers a non-blocking fd with the reactor, so that tasks can wait for it
+/// to become readable or writable.
+///
+/// The fd is assumed ready in both directions to begin with. Once an operation
+/// on it fails with `WouldBlock`, call [`AsyncFdReadyGuard::clear_ready`] on
+/// the guard it was made under, and the next [`AsyncFd::readable`] or
+/// [`AsyncFd::writable`] waits for the reactor. Readiness can be spurious, so
+/// operations should still expect `WouldBlock`.
+///
+/// The fd is deregistered, but not closed, when the `AsyncFd` is dropped;
+/// closing it is up to `T`. This works on real file descriptors, so isn't
+/// available under a simulation.
+pub struct AsyncFd<T: AsRawFd> {
+    inner: Option<T>,
+    fd: RawFd,
+    read_ready: Cell<bool>,
+    write_ready: Cell<bool>,
+}
+
+impl<T: AsRawFd> AsyncFd<T> {
+    /// Fails if the fd can't be watched with epoll, as with regular files, or
+    /// is already registered, as with a `TcpStream`'s.
+    pub fn new(inner: T) -> io::Result<Self> {
+        let fd = inner.as_raw_fd();
+        reactor::register_interest(fd, reactor::STREAM_INTEREST)?;
+        Ok(Self {
+            inner: Some(inner),
+            fd,
+            read_ready: Cell::new(true),
+            write_ready: Cell::new(true),
+        })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Deregisters the fd and hands it back.
+    pub fn into_inner(mut self) -> T {
+        let _ = reactor::deregister(self.fd);
+        self.inner.take().unwrap()
+    }
+
+    /// Waits until the fd might be readable.
+    pub async fn readable(&self) -> io::Result<AsyncFdReadyGuard<'_, T>> {
+        self.ready(Direction::Read).await
+    }
+
+    /// Waits until the fd might be writable.
+    pub async fn writable(&self) -> io::Result<AsyncFdReadyGuard<'_, T>> {
+        self.ready(Direction::Write).await
+    }
+
+    async fn ready(&self, direction: Direction) -> io::Result<AsyncFdReadyGuard<'_, T>> {
+        let ready = self.flag(direction);
+        let mut registered = false;
+        poll_fn(|cx| {
+            if ready.get() || registered || reactor::take_readiness(self.fd, direction) {
+                ready.set(true);
+                return Poll::Ready(Ok(AsyncFdReadyGuard {
+                    fd: self,
+                    direction,
+                }));
+            }
+            registered = true;
+            reactor::register_wake(self.fd, direction, cx.waker().clone())?;
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn flag(&self, direction: Direction) -> &Cell<bool> {
+        match direction {
+            Direction::Read => &self.read_ready,
+            Direction::Write => &self.write_ready,
+        }
+    }
+}
+
+impl<T: AsRawFd> AsRawFd for AsyncFd<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl<T: AsRawFd> Drop for AsyncFd<T> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = reactor::deregister(self.fd);
+        }
+    }
+}
+
+/// Says that an [`AsyncFd`] might be ready in one direction, from
+/// [`AsyncFd::readable`] or [`AsyncFd::writable`].
+pub struct AsyncFdReadyGuard<'a, T: AsRawFd> {
+    fd: &'a AsyncFd<T>,
+    direction: Direction,
+}
+
+impl<'a, T: AsRawFd> AsyncFdReadyGuard<'a, T> {
+    pub fn get_ref(&self) -> &'a AsyncFd<T> {
+        self.fd
+    }
+
+    /// Marks the fd as not ready, so waiting on it again waits for the
+    /// reactor. Only call this after an operation fails with `WouldBlock`,
+    /// or readiness that arrived in the meantime can be missed.
+    pub fn clear_ready(&mut self) {
+        self.fd.flag(self.direction).set(false);
+    }
+
+    /// Runs `f`, clearing readiness if it fails with `WouldBlock`. Returns
+    /// `Err(TryIoError)` in that case, so the caller knows to wait again.
+    pub fn try_io<R>(
+        &mut self,
+        f: impl FnOnce(&'a AsyncFd<T>) -> io::Result<R>,
+    ) -> Result<io::Result<R>, TryIoError> {
+        match f(self.fd) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.clear_ready();
+                Err(TryIoError(()))
+            }
+            res => Ok(res),
+        }
+    }
+}
+
+/// The operation passed to [`AsyncFdReadyGuard::try_io`] would have blocked.
+pub struct TryIoError(());
+
+impl fmt::Debug for TryIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TryIoError")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{executor, runtime, sys, time};
+
+    fn pipe() -> (AsyncFd<OwnedFd>, AsyncFd<OwnedFd>) {
+        let (read, write) = sys::pipe().unwrap();
+        // SAFETY: `pipe` just opened these, and nothing else owns them.
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(read), OwnedFd::from_raw_fd(write)) };
+        (AsyncFd::new(read).unwrap(), AsyncFd::new(write).unwrap())
+    }
+
+    async fn read(fd: &AsyncFd<OwnedFd>, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = fd.readable().await?;
+            if let Ok(res) = guard.try_io(|fd| sys::read(fd.as_raw_fd(), buf)) {
+                return res;
+            }
+        }
+    }
+
+    async fn write(fd: &AsyncFd<OwnedFd>, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = fd.writable().await?;
+            if let Ok(res) = guard.try_io(|fd| sys::write(fd.as_raw_fd(), buf)) {
+                return res;
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipe_round_trip() {
+        let received = runtime::run(async {
+            let (rx, tx) = pipe();
+            let writer = executor::spawn(async move {
+                time::sleep(Duration::from_millis(10)).await;
+                write(&tx, b"ping").await
+            });
+
+            let mut buf = [0; 16];
+            let n = read(&rx, &mut buf).await?;
+            assert_eq!(4, writer.await.unwrap()?);
+            io::Result::Ok(buf[..n].to_vec())
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(b"ping", &received[..]);
+    }
+
+    #[test]
+    fn test_readiness_while_not_waiting_is_kept() {
+        let received = runtime::run(async {
+            let (rx, tx) = pipe();
+            let mut buf = [0; 16];
+            let mut guard = rx.readable().await?;
+            assert!(
+                guard
+                    .try_io(|fd| sys::read(fd.as_raw_fd(), &mut buf))
+                    .is_err()
+            );
+
+            // The reactor sees the pipe become readable while we're asleep.
+            write(&tx, b"ping").await?;
+            time::sleep(Duration::from_millis(10)).await;
+            let n = read(&rx, &mut buf).await?;
+            io::Result::Ok(buf[..n].to_vec())
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(b"ping", &received[..]);
+    }
+
+    #[test]
+    fn test_writable_after_draining_full_pipe() {
+        let drained = runtime::run(async {
+            let (rx, tx) = pipe();
+            let chunk = [7; 4096];
+            let mut written = 0;
+            {
+                let mut guard = tx.writable().await?;
+                while let Ok(res) = guard.try_io(|fd| sys::write(fd.as_raw_fd(), &chunk)) {
+                    written += res?;
+                }
+            }
+
+            // The last chunk only fits once the reader has made room. Pipe
+            // writes of up to a page are all or nothing.
+            let total = written + chunk.len();
+            let reader = executor::spawn(async move {
+                time::sleep(Duration::from_millis(10)).await;
+                let mut buf = vec![0; total];
+                let mut drained = 0;
+                while drained < total {
+                    drained += read(&rx, &mut buf[drained..]).await?;
+                }
+                io::Result::Ok(drained)
+            });
+
+            assert_eq!(chunk.len(), write(&tx, &chunk).await?);
+            let drained = reader.await.unwrap()?;
+            io::Result::Ok(drained == total)
+        })
+        .unwrap()
+        .unwrap();
+        assert!(drained);
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use super::{AsyncRead, AsyncWrite};
+use crate::executor::{self, BlockingHandle};
+
+/// The most each call on the blocking pool reads or writes.
+const MAX_BUF: usize = 64 * 1024;
+
+/// Data on its way to or from the blocking pool.
+#[derive(Default)]
+pub(crate) struct Buf {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Buf {
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    /// How much has been read ahead but not handed out yet.
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.data.clear();
+        self.pos = 0;
+    }
+
+    /// Hands out buffered data, returning how much was copied.
+    fn copy_to(&mut self, dst: &mut [u8]) -> usize {
+        let n = (self.data.len() - self.pos).min(dst.len());
+        dst[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        n
+    }
+
+    fn copy_from(&mut self, src: &[u8]) -> usize {
+        let n = src.len().min(MAX_BUF);
+        self.data.clear();
+        self.data.extend_from_slice(&src[..n]);
+        self.pos = 0;
+        n
+    }
+
+    fn read_from<T: Read>(&mut self, inner: &mut T, len: usize) -> io::Result<usize> {
+        self.data.resize(len.min(MAX_BUF), 0);
+        self.pos = 0;
+        let res = loop {
+            match inner.read(&mut self.data) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                res => break res,
+            }
+        };
+        self.data.truncate(*res.as_ref().unwrap_or(&0));
+        res
+    }
+
+    fn write_to<T: Write>(&mut self, inner: &mut T) -> io::Result<()> {
+        let res = inner.write_all(&self.data[self.pos..]);
+        self.clear();
+        res
+    }
+}
+
+enum State<T> {
+    Idle(Option<Buf>),
+    Busy(BlockingHandle<(Operation, Buf, T)>),
+}
+
+/// What the blocking pool last did, and how it went.
+enum Operation {
+    Read(io::Result<usize>),
+    Write(io::Result<()>),
+    Flush(io::Result<()>),
+}
+
+/// Runs a blocking reader or writer's calls on the blocking pool, one at a
+/// time. Reads go through a buffer, and writes return as soon as the data has
+/// been copied, so any error writing it shows up on the next call.
+pub(crate) struct Blocking<T> {
+    inner: Option<T>,
+    state: State<T>,
+    /// An error from a write that finished after the call that started it.
+    write_err: Option<io::Error>,
+}
+
+// The inner value moves to and from the pool by value, and is never pinned.
+impl<T> Unpin for Blocking<T> {}
+
+impl<T: Send + 'static> Blocking<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Self {
+            inner: Some(inner),
+            state: State::Idle(Some(Buf::default())),
+            write_err: None,
+        }
+    }
+
+    /// Waits for the pool to finish with the inner value, keeping whatever was
+    /// buffered.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<(Operation, Buf)> {
+        let State::Busy(handle) = &mut self.state else {
+            unreachable!("poll_idle while idle");
+        };
+        let (op, buf, inner) =
+            ready!(Pin::new(handle).poll(cx)).expect("blocking IO panicked on the blocking pool");
+        self.inner = Some(inner);
+        Poll::Ready((op, buf))
+    }
+
+    /// Gives the inner value to a closure on the blocking pool.
+    fn run<F>(&mut self, mut buf: Buf, f: F)
+    where
+        F: FnOnce(&mut T, &mut Buf) -> Operation + Send + 'static,
+    {
+        let mut inner = self.inner.take().unwrap();
+        self.state = State::Busy(executor::spawn_blocking(move || {
+            let op = f(&mut inner, &mut buf);
+            (op, buf, inner)
+        }));
+    }
+
+    /// Waits out any operation in flight. Read data that hasn't been handed
+    /// out yet is returned in the buffer.
+    pub(crate) fn poll_complete(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Buf>> {
+        loop {
+            match &mut self.state {
+                State::Idle(buf) => {
+                    if let Some(e) = self.write_err.take() {
+                        return Poll::Ready(Err(e));
+                    }
+                    return Poll::Ready(Ok(buf.take().unwrap_or_default()));
+                }
+                State::Busy(_) => {
+                    let (op, buf) = ready!(self.poll_idle(cx));
+                    if let Operation::Write(Err(e)) | Operation::Flush(Err(e)) = op {
+                        self.write_err = Some(e);
+                    }
+                    self.state = State::Idle(Some(buf));
+                }
+            }
+        }
+    }
+
+    /// Puts back the buffer taken by [`Blocking::poll_complete`].
+    pub(crate) fn restore(&mut self, buf: Buf) {
+        self.state = State::Idle(Some(buf));
+    }
+}
+
+impl<T: Read + Send + 'static> AsyncRead for Blocking<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        dst: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Idle(buf) => {
+                    let mut buf = buf.take().unwrap();
+                    if !buf.is_empty() {
+                        let n = buf.copy_to(dst);
+                        this.state = State::Idle(Some(buf));
+                        return Poll::Ready(Ok(n));
+                    }
+                    if dst.is_empty() {
+                        this.state = State::Idle(Some(buf));
+                        return Poll::Ready(Ok(0));
+                    }
+
+                    let len = dst.len();
+                    this.run(buf, move |inner, buf| {
+                        Operation::Read(buf.read_from(inner, len))
+                    });
+                }
+                State::Busy(_) => {
+                    let (op, mut buf) = ready!(this.poll_idle(cx));
+                    let res = match op {
+                        Operation::Read(Ok(_)) => Ok(buf.copy_to(dst)),
+                        Operation::Read(Err(e)) => Err(e),
+                        // A write left over from before; go around and read.
+                        Operation::Write(res) | Operation::Flush(res) => {
+                            if let Err(e) = res {
+                                this.write_err = Some(e);
+                            }
+                            this.state = State::Idle(Some(buf));
+                            continue;
+                        }
+                    };
+                    this.state = State::Idle(Some(buf));
+                    return Poll::Ready(res);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Write + Send + 'static> AsyncWrite for Blocking<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        src: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut buf = ready!(this.poll_complete(cx))?;
+        if src.is_empty() {
+            this.restore(buf);
+            return Poll::Ready(Ok(0));
+        }
+
+        // Anything read ahead would be out of date once this write lands.
+        buf.clear();
+        let n = buf.copy_from(src);
+        this.run(buf, |inner, buf| Operation::Write(buf.write_to(inner)));
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match &this.state {
+                State::Idle(_) => {
+                    let buf = ready!(this.poll_complete(cx))?;
+                    this.run(buf, |inner, _| Operation::Flush(inner.flush()));
+                }
+                State::Busy(_) => {
+                    let (op, buf) = ready!(this.poll_idle(cx));
+                    this.state = State::Idle(Some(buf));
+                    match op {
+                        Operation::Flush(res) => return Poll::Ready(res),
+                        Operation::Write(Err(e)) => return Poll::Ready(Err(e)),
+                        Operation::Read(_) | Operation::Write(Ok(())) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
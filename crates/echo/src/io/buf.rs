@@ -0,0 +1,562 @@
+use std::future::{Future, poll_fn};
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use super::{AsyncRead, AsyncWrite};
+use crate::stream::Stream;
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// A reader with an internal buffer, which lets callers look at what has been
+/// read before deciding how much of it to take.
+pub trait AsyncBufRead: AsyncRead {
+    /// Returns the buffered data, reading more from the underlying source if
+    /// the buffer is empty. An empty slice means the end of the stream.
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>>;
+
+    /// Marks `amt` bytes of the buffer as used, so they aren't returned by
+    /// `poll_fill_buf` again.
+    fn consume(self: Pin<&mut Self>, amt: usize);
+}
+
+impl<T: AsyncBufRead + Unpin + ?Sized> AsyncBufRead for &mut T {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut **self.get_mut()).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut **self).consume(amt)
+    }
+}
+
+impl<T: AsyncBufRead + Unpin + ?Sized> AsyncBufRead for Box<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut **self.get_mut()).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut **self).consume(amt)
+    }
+}
+
+impl AsyncBufRead for &[u8] {
+    fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Poll::Ready(Ok(*self.get_mut()))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        *self = &self[amt..];
+    }
+}
+
+/// Async helpers for every [`AsyncBufRead`].
+pub trait AsyncBufReadExt: AsyncBufRead {
+    fn fill_buf(&mut self) -> FillBuf<'_, Self>
+    where
+        Self: Unpin,
+    {
+        FillBuf { reader: Some(self) }
+    }
+
+    fn consume(&mut self, amt: usize)
+    where
+        Self: Unpin,
+    {
+        Pin::new(self).consume(amt)
+    }
+
+    /// Reads up to and including `byte`, appending to `buf`. Returns how many
+    /// bytes were read, which is zero at the end of the stream.
+    fn read_until<'a>(
+        &'a mut self,
+        byte: u8,
+        buf: &'a mut Vec<u8>,
+    ) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        let mut read = 0;
+        poll_fn(move |cx| poll_read_until(Pin::new(&mut *self), cx, byte, buf, &mut read))
+    }
+
+    /// Reads up to and including the next `\n`, appending to `buf`. Fails
+    /// with `InvalidData` if the line isn't UTF-8, in which case `buf` is left
+    /// as it was.
+    ///
+    /// Anything read so far is lost if the future is dropped before it
+    /// completes.
+    fn read_line<'a>(
+        &'a mut self,
+        buf: &'a mut String,
+    ) -> impl Future<Output = io::Result<usize>> + 'a
+    where
+        Self: Unpin,
+    {
+        let mut line = Vec::new();
+        let mut read = 0;
+        poll_fn(move |cx| {
+            let n = ready!(poll_read_until(
+                Pin::new(&mut *self),
+                cx,
+                b'\n',
+                &mut line,
+                &mut read
+            ))?;
+            let line = String::from_utf8(std::mem::take(&mut line))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            buf.push_str(&line);
+            Poll::Ready(Ok(n))
+        })
+    }
+
+    /// Turns the reader into a sequence of lines, without their line endings.
+    fn lines(self) -> Lines<Self>
+    where
+        Self: Sized,
+    {
+        Lines {
+            reader: self,
+            line: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: AsyncBufRead + ?Sized> AsyncBufReadExt for R {}
+
+/// Keeps going until `byte` has been read or the stream ends, counting what
+/// it reads in `read`, which is reset when it completes.
+fn poll_read_until<R: AsyncBufRead + ?Sized>(
+    mut reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    byte: u8,
+    buf: &mut Vec<u8>,
+    read: &mut usize,
+) -> Poll<io::Result<usize>> {
+    loop {
+        let (done, used) = {
+            let available = ready!(reader.as_mut().poll_fill_buf(cx))?;
+            match available.iter().position(|b| *b == byte) {
+                Some(i) => {
+                    buf.extend_from_slice(&available[..=i]);
+                    (true, i + 1)
+                }
+                None => {
+                    buf.extend_from_slice(available);
+                    (available.is_empty(), available.len())
+                }
+            }
+        };
+        reader.as_mut().consume(used);
+        *read += used;
+        if done {
+            return Poll::Ready(Ok(std::mem::take(read)));
+        }
+    }
+}
+
+pub struct FillBuf<'a, R: ?Sized> {
+    reader: Option<&'a mut R>,
+}
+
+impl<'a, R: AsyncBufRead + Unpin + ?Sized> Future for FillBuf<'a, R> {
+    type Output = io::Result<&'a [u8]>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let reader = self.reader.take().expect("polled after completion");
+        match Pin::new(&mut *reader).poll_fill_buf(cx) {
+            Poll::Ready(Ok(slice)) => {
+                // SAFETY: the slice borrows from `reader`, which lives for
+                // `'a` and isn't used again. The borrow checker can't see that
+                // only the pending case gives the reader back.
+                let slice = unsafe { std::mem::transmute::<&[u8], &'a [u8]>(slice) };
+                Poll::Ready(Ok(slice))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => {
+                self.reader = Some(reader);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The lines of an [`AsyncBufRead`], from [`AsyncBufReadExt::lines`].
+pub struct Lines<R> {
+    reader: R,
+    line: Vec<u8>,
+    read: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> Lines<R> {
+    /// Returns the next line without its `\n` or `\r\n`, or `None` at the end
+    /// of the stream.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        poll_fn(|cx| self.poll_next_line(cx)).await
+    }
+
+    pub fn poll_next_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<String>>> {
+        let read = ready!(poll_read_until(
+            Pin::new(&mut self.reader),
+            cx,
+            b'\n',
+            &mut self.line,
+            &mut self.read,
+        ))?;
+        if read == 0 {
+            return Poll::Ready(Ok(None));
+        }
+
+        let mut line = std::mem::take(&mut self.line);
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        let line =
+            String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Poll::Ready(Ok(Some(line)))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for Lines<R> {
+    type Item = io::Result<String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_line(cx).map(Result::transpose)
+    }
+}
+
+/// Adds a buffer to a reader, so that many small reads turn into a few large
+/// ones.
+pub struct BufReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: AsyncRead> BufReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        Self {
+            inner,
+            buf: vec![0; capacity].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// The data that has been read but not yet consumed.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+
+    /// Returns the underlying reader. Anything still buffered is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for BufReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Reads at least as big as the buffer gain nothing from going through
+        // it.
+        if this.pos == this.filled && buf.len() >= this.buf.len() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        let available = ready!(Pin::new(&mut *this).poll_fill_buf(cx))?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        Pin::new(this).consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for BufReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos == this.filled {
+            this.filled = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut this.buf))?;
+            this.pos = 0;
+        }
+        Poll::Ready(Ok(&this.buf[this.pos..this.filled]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.pos = (this.pos + amt).min(this.filled);
+    }
+}
+
+/// Writes pass straight through, so a buffered stream can still be written to.
+impl<R: AsyncWrite + Unpin> AsyncWrite for BufReader<R> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Adds a buffer to a writer, so that many small writes turn into a few large
+/// ones.
+///
+/// Buffered data is only written out by `flush` or `shutdown`. Anything still
+/// buffered when the writer is dropped is lost.
+pub struct BufWriter<W> {
+    inner: W,
+    buf: Vec<u8>,
+    /// How much of `buf` has already made it to `inner`.
+    written: usize,
+}
+
+impl<W: AsyncWrite> BufWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The data that has been written but not yet passed on.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+
+    /// Returns the underlying writer. Anything still buffered is lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> BufWriter<W> {
+    fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.buf.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buf[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for BufWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.buf.len() + buf.len() > this.buf.capacity() {
+            ready!(this.poll_flush_buf(cx))?;
+        }
+
+        if buf.len() >= this.buf.capacity() {
+            Pin::new(&mut this.inner).poll_write(cx, buf)
+        } else {
+            this.buf.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let total: usize = bufs.iter().map(|b| b.len()).sum();
+        if this.buf.len() + total > this.buf.capacity() {
+            ready!(this.poll_flush_buf(cx))?;
+        }
+
+        if total >= this.buf.capacity() {
+            Pin::new(&mut this.inner).poll_write_vectored(cx, bufs)
+        } else {
+            for buf in bufs {
+                this.buf.extend_from_slice(buf);
+            }
+            Poll::Ready(Ok(total))
+        }
+    }
+
+    /// Small buffers are gathered into the buffer, whatever the writer
+    /// underneath does.
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    /// Writes out anything buffered before shutting down the writer.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Reads pass straight through, so a buffered stream can still be read from.
+impl<W: AsyncRead + Unpin> AsyncRead for BufWriter<W> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Records each write it is given.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl AsyncWrite for Writes {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut().0.push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_line_across_fills() {
+        let mut reader = BufReader::with_capacity(4, &b"hello\nworld"[..]);
+        let mut line = String::new();
+        assert_eq!(6, reader.read_line(&mut line).await.unwrap());
+        assert_eq!(5, reader.read_line(&mut line).await.unwrap());
+        assert_eq!(0, reader.read_line(&mut line).await.unwrap());
+        assert_eq!("hello\nworld", line);
+
+        let mut reader = BufReader::new(&b"\xff\n"[..]);
+        let err = reader.read_line(&mut line).await.unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!("hello\nworld", line);
+    }
+
+    #[tokio::test]
+    async fn test_read_until_and_fill_buf() {
+        let mut reader = BufReader::with_capacity(3, &b"a,bc,def"[..]);
+        let mut buf = Vec::new();
+        reader.read_until(b',', &mut buf).await.unwrap();
+        assert_eq!(b"a,", &buf[..]);
+
+        // Only what's left of the first fill, rather than reading more.
+        assert_eq!(b"b", reader.fill_buf().await.unwrap());
+        reader.consume(1);
+        assert_eq!(b"c,d", reader.fill_buf().await.unwrap());
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(b"c,def", &rest[..]);
+    }
+
+    #[tokio::test]
+    async fn test_lines() {
+        let mut lines = BufReader::with_capacity(2, &b"one\r\ntwo\n\nthree"[..]).lines();
+        let mut all = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            all.push(line);
+        }
+        assert_eq!(vec!["one", "two", "", "three"], all);
+    }
+
+    #[tokio::test]
+    async fn test_buf_writer_coalesces_and_flushes_on_shutdown() {
+        let mut writer = BufWriter::with_capacity(8, Writes::default());
+        writer.write_all(b"ab").await.unwrap();
+        writer.write_all(b"cd").await.unwrap();
+        assert!(writer.get_ref().0.is_empty());
+
+        // Doesn't fit alongside what's buffered, and is too big to buffer.
+        writer.write_all(b"0123456789").await.unwrap();
+        writer.write_all(b"ef").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let writes = writer.into_inner().0;
+        assert_eq!(
+            vec![b"abcd".to_vec(), b"0123456789".to_vec(), b"ef".to_vec()],
+            writes
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use super::{AsyncRead, AsyncWrite};
+
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Copies one direction of a stream, from reading through to shutting down
+/// the writer.
+struct CopyBuffer {
+    buf: Box<[u8]>,
+    /// The part of `buf` that has been read but not yet written.
+    pos: usize,
+    cap: usize,
+    amt: u64,
+    read_done: bool,
+    done: bool,
+}
+
+impl CopyBuffer {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            amt: 0,
+            read_done: false,
+            done: false,
+        }
+    }
+
+    fn poll_copy<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<io::Result<u64>>
+    where
+        R: AsyncRead + ?Sized,
+        W: AsyncWrite + ?Sized,
+    {
+        while !self.done {
+            if self.pos == self.cap && !self.read_done {
+                let n = match reader.as_mut().poll_read(cx, &mut self.buf) {
+                    Poll::Ready(res) => res?,
+                    Poll::Pending => {
+                        // Don't leave what has been written so far sitting in
+                        // a buffer while waiting for more.
+                        ready!(writer.as_mut().poll_flush(cx))?;
+                        return Poll::Pending;
+                    }
+                };
+                self.read_done = n == 0;
+                self.pos = 0;
+                self.cap = n;
+            }
+
+            while self.pos < self.cap {
+                let n = ready!(
+                    writer
+                        .as_mut()
+                        .poll_write(cx, &self.buf[self.pos..self.cap])
+                )?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.pos += n;
+                self.amt += n as u64;
+            }
+
+            if self.read_done {
+                ready!(writer.as_mut().poll_shutdown(cx))?;
+                self.done = true;
+            }
+        }
+        Poll::Ready(Ok(self.amt))
+    }
+}
+
+/// Copies everything from `reader` to `writer` until the reader reaches the
+/// end of the stream, then shuts the writer down so the end is passed on.
+/// Returns how many bytes were copied.
+pub async fn copy<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = CopyBuffer::new();
+    poll_fn(|cx| buf.poll_copy(cx, Pin::new(&mut *reader), Pin::new(&mut *writer))).await
+}
+
+/// Copies between `a` and `b` in both directions at once, as a proxy would.
+/// When one side reaches the end of its stream, the other side's write half
+/// is shut down, and the copy carries on in the other direction until it
+/// ends too. Returns how many bytes went from `a` to `b` and from `b` to `a`.
+pub async fn copy_bidirectional<A, B>(a: &mut A, b: &mut B) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let (mut a_to_b, mut b_to_a) = (CopyBuffer::new(), CopyBuffer::new());
+    poll_fn(|cx| {
+        let a_to_b = a_to_b.poll_copy(cx, Pin::new(&mut *a), Pin::new(&mut *b))?;
+        let b_to_a = b_to_a.poll_copy(cx, Pin::new(&mut *b), Pin::new(&mut *a))?;
+        Poll::Ready(Ok((ready!(a_to_b), ready!(b_to_a))))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Reads from `input` and writes at most `max_write` bytes at a time to
+    /// `output`.
+    struct Peer {
+        input: &'static [u8],
+        output: Vec<u8>,
+        max_write: usize,
+        shut_down: bool,
+    }
+
+    impl Peer {
+        fn new(input: &'static [u8], max_write: usize) -> Self {
+            Self {
+                input,
+                output: Vec::new(),
+                max_write,
+                shut_down: false,
+            }
+        }
+    }
+
+    impl AsyncRead for Peer {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.get_mut().input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Peer {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            assert!(!this.shut_down);
+            let n = buf.len().min(this.max_write);
+            this.output.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().shut_down = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_handles_partial_writes() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+        let mut reader = &data[..];
+        let mut writer = Peer::new(b"", 7);
+        assert_eq!(20_000, copy(&mut reader, &mut writer).await.unwrap());
+        assert_eq!(data, writer.output);
+        assert!(writer.shut_down);
+    }
+
+    #[tokio::test]
+    async fn test_copy_bidirectional() {
+        let mut a = Peer::new(b"from a", 2);
+        let mut b = Peer::new(b"from b, which is longer", 3);
+        let (a_to_b, b_to_a) = copy_bidirectional(&mut a, &mut b).await.unwrap();
+        assert_eq!((6, 23), (a_to_b, b_to_a));
+        assert_eq!(b"from a", &b.output[..]);
+        assert_eq!(b"from b, which is longer", &a.output[..]);
+        assert!(a.shut_down && b.shut_down);
+    }
+}
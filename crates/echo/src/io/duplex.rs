@@ -0,0 +1,224 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use super::{AsyncRead, AsyncWrite};
+use crate::executor::{self, Waiting};
+
+/// Creates a pair of connected in-memory streams. Whatever is written to one
+/// can be read from the other, and each direction buffers up to `max_buf`
+/// bytes before writes wait for the reader to catch up.
+///
+/// Dropping or shutting down one end is the end of the stream for the other
+/// end's reads, and dropping one end makes the other end's writes fail with
+/// `BrokenPipe`.
+pub fn duplex(max_buf: usize) -> (DuplexStream, DuplexStream) {
+    assert!(max_buf > 0, "duplex needs room for at least one byte");
+    let a_to_b = Rc::new(RefCell::new(Pipe::new(max_buf)));
+    let b_to_a = Rc::new(RefCell::new(Pipe::new(max_buf)));
+    let a = DuplexStream {
+        rx: b_to_a.clone(),
+        tx: a_to_b.clone(),
+    };
+    let b = DuplexStream {
+        rx: a_to_b,
+        tx: b_to_a,
+    };
+    (a, b)
+}
+
+/// One direction of a duplex.
+struct Pipe {
+    buf: VecDeque<u8>,
+    max_buf: usize,
+    /// Set once the writing end shuts down or is dropped.
+    closed: bool,
+    /// Set once the reading end is dropped, so writes fail.
+    reader_closed: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Pipe {
+    fn new(max_buf: usize) -> Self {
+        Self {
+            buf: VecDeque::new(),
+            max_buf,
+            closed: false,
+            reader_closed: false,
+            reader: None,
+            writer: None,
+        }
+    }
+
+    fn close_write(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+
+    fn close_read(&mut self) {
+        self.reader_closed = true;
+        if let Some(waker) = self.writer.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One end of an in-memory stream, from [`duplex`].
+pub struct DuplexStream {
+    rx: Rc<RefCell<Pipe>>,
+    tx: Rc<RefCell<Pipe>>,
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut rx = self.rx.borrow_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if rx.buf.is_empty() {
+            if rx.closed {
+                return Poll::Ready(Ok(0));
+            }
+            rx.reader = Some(cx.waker().clone());
+            executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+            return Poll::Pending;
+        }
+
+        let n = rx.buf.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(rx.buf.drain(..n)) {
+            *dst = src;
+        }
+        if let Some(waker) = rx.writer.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut tx = self.tx.borrow_mut();
+        if tx.reader_closed || tx.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let space = tx.max_buf - tx.buf.len();
+        if space == 0 {
+            tx.writer = Some(cx.waker().clone());
+            executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+            return Poll::Pending;
+        }
+
+        let n = space.min(buf.len());
+        tx.buf.extend(&buf[..n]);
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.tx.borrow_mut().close_write();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.tx.borrow_mut().close_write();
+        self.rx.borrow_mut().close_read();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::executor;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::runtime::Builder;
+    use crate::time;
+
+    #[test]
+    fn test_backpressure() {
+        let (received, blocked_for) = Builder::simulation(0)
+            .run(async {
+                let (mut a, mut b) = duplex(4);
+                let writer = executor::spawn(async move {
+                    let start = time::now();
+                    a.write_all(b"0123456789").await?;
+                    io::Result::Ok(time::now() - start)
+                });
+
+                // Only what fits in the buffer gets through until we read.
+                time::sleep(Duration::from_millis(10)).await;
+                let mut buf = [0; 16];
+                assert_eq!(4, b.read(&mut buf).await?);
+
+                let mut received = buf[..4].to_vec();
+                b.read_to_end(&mut received).await?;
+                io::Result::Ok((received, writer.await.unwrap()?))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(b"0123456789", &received[..]);
+        assert_eq!(Duration::from_millis(10), blocked_for);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_and_drop() {
+        let (mut a, mut b) = duplex(16);
+        a.write_all(b"last words").await.unwrap();
+        a.shutdown().await.unwrap();
+        let err = a.write(b"more").await.unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+
+        // The other direction is still open until `a` is dropped.
+        b.write_all(b"reply").await.unwrap();
+        let mut buf = Vec::new();
+        b.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(b"last words", &buf[..]);
+
+        drop(a);
+        let err = b.write(b"anyone?").await.unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    }
+
+    #[test]
+    fn test_eof_when_peer_drops() {
+        let n = Builder::simulation(0)
+            .run(async {
+                let (a, mut b) = duplex(16);
+                let reader = executor::spawn(async move { b.read(&mut [0; 4]).await });
+                time::sleep(Duration::from_millis(1)).await;
+                drop(a);
+                reader.await.unwrap()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(0, n);
+    }
+}
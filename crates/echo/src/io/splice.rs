@@ -0,0 +1,288 @@
+use std::future::poll_fn;
+use std::io;
+use std::os::fd::RawFd;
+use std::task::Poll;
+
+use crate::reactor::{self, Direction};
+use crate::{sys, syscall};
+
+/// How much to move per `splice` or `sendfile` call. Matches the default pipe
+/// capacity, so a single splice can fill the pipe.
+const CHUNK: usize = 64 * 1024;
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Copies everything from `from` to `to` without passing it through
+/// userspace, returning how many bytes were copied. Both fds must be
+/// non-blocking, or regular files.
+///
+/// Regular files are sent with `sendfile(2)`, and anything else is spliced
+/// through a pipe with `splice(2)`. If the kernel can't do either for these
+/// fds, it falls back to copying through a buffer. Unlike [`super::copy`],
+/// `to` is left open when `from` reaches the end of the stream.
+///
+/// This works on real file descriptors, so isn't available under a
+/// simulation.
+pub async fn splice_copy(from: RawFd, to: RawFd) -> io::Result<u64> {
+    let _from_watch = Watch::new(from)?;
+    let _to_watch = if to == from {
+        None
+    } else {
+        Some(Watch::new(to)?)
+    };
+
+    let mut amt = 0;
+    let fell_back = if sys::is_regular_file(from)? {
+        send_file(from, to, &mut amt).await?
+    } else {
+        splice_through_pipe(from, to, &mut amt).await?
+    };
+
+    if fell_back {
+        amt += copy_buffered(from, to, u64::MAX).await?;
+    }
+    Ok(amt)
+}
+
+/// Returns whether the rest of the copy needs to fall back to a buffer.
+async fn send_file(from: RawFd, to: RawFd, amt: &mut u64) -> io::Result<bool> {
+    loop {
+        match sys::sendfile(to, from, CHUNK) {
+            Ok(0) => return Ok(false),
+            Ok(n) => *amt += n as u64,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                readiness(to, Direction::Write).await?
+            }
+            Err(e) if unsupported(&e) => return Ok(true),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns whether the rest of the copy needs to fall back to a buffer.
+async fn splice_through_pipe(from: RawFd, to: RawFd, amt: &mut u64) -> io::Result<bool> {
+    let pipe = Pipe::new()?;
+    let mut in_pipe = 0;
+    loop {
+        if in_pipe == 0 {
+            match sys::splice(from, pipe.write, CHUNK) {
+                Ok(0) => return Ok(false),
+                Ok(n) => in_pipe = n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    readiness(from, Direction::Read).await?;
+                    continue;
+                }
+                Err(e) if unsupported(&e) => return Ok(true),
+                Err(e) => return Err(e),
+            }
+        }
+
+        match sys::splice(pipe.read, to, in_pipe) {
+            Ok(n) => {
+                in_pipe -= n;
+                *amt += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                readiness(to, Direction::Write).await?
+            }
+            Err(e) if unsupported(&e) => {
+                // Empty the pipe the slow way before carrying on from `from`.
+                *amt += copy_buffered(pipe.read, to, in_pipe as u64).await?;
+                return Ok(true);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Copies up to `limit` bytes through a buffer, stopping early if `from`
+/// reaches the end of the stream.
+async fn copy_buffered(from: RawFd, to: RawFd, limit: u64) -> io::Result<u64> {
+    let mut buf = vec![0; BUF_SIZE];
+    let mut amt = 0;
+    while amt < limit {
+        let len = (limit - amt).min(BUF_SIZE as u64) as usize;
+        let n = match sys::read(from, &mut buf[..len]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                readiness(from, Direction::Read).await?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut written = 0;
+        while written < n {
+            match sys::write(to, &buf[written..n]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(w) => written += w,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    readiness(to, Direction::Write).await?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        amt += n as u64;
+    }
+    Ok(amt)
+}
+
+/// Whether the kernel can't splice or sendfile between these kinds of fd.
+fn unsupported(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS))
+}
+
+/// Waits for the reactor to report that `fd` may be ready in `direction`.
+async fn readiness(fd: RawFd, direction: Direction) -> io::Result<()> {
+    let mut registered = false;
+    poll_fn(|cx| {
+        if registered {
+            return Poll::Ready(Ok(()));
+        }
+        registered = true;
+        reactor::register_wake(fd, direction, cx.waker().clone())?;
+        Poll::Pending
+    })
+    .await
+}
+
+/// Makes sure the reactor is watching an fd for the length of the copy.
+/// Fds it already knows about, like those of a `TcpStream`, are left alone.
+struct Watch {
+    fd: RawFd,
+    added: bool,
+}
+
+impl Watch {
+    fn new(fd: RawFd) -> io::Result<Self> {
+        let added = match reactor::register_interest(fd, reactor::STREAM_INTEREST) {
+            Ok(_) => true,
+            Err(e) if e.raw_os_error() == Some(libc::EEXIST) => false,
+            // Regular files can't be watched, but never block either.
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => false,
+            Err(e) => return Err(e),
+        };
+        Ok(Self { fd, added })
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if self.added {
+            let _ = reactor::deregister(self.fd);
+        }
+    }
+}
+
+struct Pipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Pipe {
+    fn new() -> io::Result<Self> {
+        let (read, write) = sys::pipe()?;
+        Ok(Self { read, write })
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let _ = syscall!(close(self.read));
+        let _ = syscall!(close(self.write));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, OpenOptions};
+    use std::net::{Shutdown, SocketAddr};
+    use std::os::fd::AsRawFd;
+
+    use super::*;
+    use crate::executor;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::runtime;
+    use crate::tcp::{TcpListener, TcpStream};
+
+    fn payload() -> Vec<u8> {
+        (0..300_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn connected_pair() -> io::Result<(TcpStream, TcpStream)> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+        Ok((client, server))
+    }
+
+    #[test]
+    fn test_file_to_socket() {
+        let path = std::env::temp_dir().join(format!("echo-sendfile-{}", std::process::id()));
+        fs::write(&path, payload()).unwrap();
+
+        let file = fs::File::open(&path).unwrap();
+        let received = runtime::run(async move {
+            let (mut client, mut server) = connected_pair().await?;
+            let reader = executor::spawn(async move {
+                let mut received = Vec::new();
+                server.read_to_end(&mut received).await.map(|_| received)
+            });
+
+            let copied = splice_copy(file.as_raw_fd(), client.raw_fd()?).await?;
+            assert_eq!(payload().len() as u64, copied);
+            client.shutdown(Shutdown::Write)?;
+            reader.await.unwrap()
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(payload(), received.unwrap());
+    }
+
+    #[test]
+    fn test_socket_to_socket() {
+        let received = runtime::run(async {
+            let (mut a, b) = connected_pair().await?;
+            let (mut c, mut d) = connected_pair().await?;
+            let writer = executor::spawn(async move {
+                a.write_all(&payload()).await?;
+                a.shutdown(Shutdown::Write)
+            });
+            let reader = executor::spawn(async move {
+                let mut received = Vec::new();
+                d.read_to_end(&mut received).await.map(|_| received)
+            });
+
+            splice_copy(b.raw_fd()?, c.raw_fd()?).await?;
+            c.shutdown(Shutdown::Write)?;
+            writer.await.unwrap()?;
+            reader.await.unwrap()
+        })
+        .unwrap();
+        assert_eq!(payload(), received.unwrap());
+    }
+
+    #[test]
+    fn test_falls_back_when_splice_is_unsupported() {
+        // Splicing into a file opened for appending isn't supported.
+        let path = std::env::temp_dir().join(format!("echo-splice-append-{}", std::process::id()));
+        fs::write(&path, b"").unwrap();
+        let file = OpenOptions::new().append(true).open(&path).unwrap();
+
+        let copied = runtime::run(async move {
+            let (mut client, server) = connected_pair().await?;
+            let writer = executor::spawn(async move {
+                client.write_all(&payload()).await?;
+                client.shutdown(Shutdown::Write)
+            });
+            let copied = splice_copy(server.raw_fd()?, file.as_raw_fd()).await?;
+            writer.await.unwrap()?;
+            io::Result::Ok(copied)
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(payload().len() as u64, copied);
+        assert_eq!(payload(), fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
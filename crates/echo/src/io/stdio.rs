@@ -0,0 +1,331 @@
+use std::io::{self, Read, Write};
+use std::os::fd::RawFd;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use libc::c_int;
+
+use super::blocking::Blocking;
+use super::{AsyncRead, AsyncWrite};
+use crate::reactor::{self, Direction};
+use crate::sys::{self, FileId};
+
+/// The process's standard input.
+///
+/// Pipes, ttys and sockets are switched to non-blocking mode and waited on
+/// through the reactor, for as long as any `Stdin`, `Stdout` or `Stderr` for
+/// them exists. The mode belongs to the open file, which may be shared with
+/// other processes and with `std::io`'s handles, whose calls can then fail
+/// with `WouldBlock`. Regular files, and anything else that can't be made
+/// non-blocking, are read on the blocking pool instead. Waiting on the reactor
+/// means pipes and ttys can't be used under a simulation.
+pub fn stdin() -> Stdin {
+    Stdin {
+        inner: Stdio::new(libc::STDIN_FILENO),
+    }
+}
+
+/// The process's standard output, set up as for [`stdin`]. Writes aren't
+/// buffered.
+pub fn stdout() -> Stdout {
+    Stdout {
+        inner: Stdio::new(libc::STDOUT_FILENO),
+    }
+}
+
+/// The process's standard error, set up as for [`stdin`].
+pub fn stderr() -> Stderr {
+    Stderr {
+        inner: Stdio::new(libc::STDERR_FILENO),
+    }
+}
+
+pub struct Stdin {
+    inner: Stdio,
+}
+
+pub struct Stdout {
+    inner: Stdio,
+}
+
+pub struct Stderr {
+    inner: Stdio,
+}
+
+impl AsyncRead for Stdin {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+macro_rules! impl_async_write {
+    ($ty:ty) => {
+        impl AsyncWrite for $ty {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+            }
+        }
+    };
+}
+
+impl_async_write!(Stdout);
+impl_async_write!(Stderr);
+
+enum Stdio {
+    Nonblocking(NonblockingFd),
+    Blocking(Blocking<RawFile>),
+}
+
+impl Stdio {
+    fn new(fd: RawFd) -> Self {
+        if let Ok(false) = sys::is_regular_file(fd)
+            && let Ok(fd) = NonblockingFd::new(fd)
+        {
+            return Self::Nonblocking(fd);
+        }
+        Self::Blocking(Blocking::new(RawFile(fd)))
+    }
+}
+
+impl AsyncRead for Stdio {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Nonblocking(fd) => fd.poll_io(cx, Direction::Read, |fd| sys::read(fd, buf)),
+            Self::Blocking(blocking) => Pin::new(blocking).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stdio {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Nonblocking(fd) => fd.poll_io(cx, Direction::Write, |fd| sys::write(fd, buf)),
+            Self::Blocking(blocking) => Pin::new(blocking).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Nonblocking(_) => Poll::Ready(Ok(())),
+            Self::Blocking(blocking) => Pin::new(blocking).poll_flush(cx),
+        }
+    }
+
+    /// Standard streams are never closed, so this only flushes.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// The files this process has made non-blocking, by device and inode, with
+/// how many handles are using each and the flags it had before the first.
+/// Keyed by file rather than fd, as `dup`'d fds share their mode.
+static NONBLOCKING: Mutex<Vec<(FileId, usize, c_int)>> = Mutex::new(Vec::new());
+
+/// An fd in non-blocking mode, which is undone when the last one for its file
+/// is dropped. Never closes the fd.
+struct NonblockingFd {
+    fd: RawFd,
+    file: FileId,
+}
+
+impl NonblockingFd {
+    fn new(fd: RawFd) -> io::Result<Self> {
+        let file = sys::file_id(fd)?;
+        let mut files = NONBLOCKING.lock().unwrap();
+        match files.iter_mut().find(|(f, _, _)| *f == file) {
+            Some((_, users, _)) => *users += 1,
+            None => {
+                let flags = sys::get_fd_flags(fd)?;
+                if flags & libc::O_NONBLOCK == 0 {
+                    sys::set_fd_flags(fd, flags | libc::O_NONBLOCK)?;
+                }
+                files.push((file, 1, flags));
+            }
+        }
+        Ok(Self { fd, file })
+    }
+
+    fn poll_io<T>(
+        &self,
+        cx: &mut Context<'_>,
+        direction: Direction,
+        mut op: impl FnMut(RawFd) -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            match op(self.fd) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => return Poll::Ready(res),
+            }
+
+            // Registered lazily, as handles can outlive a runtime. The fd stays
+            // registered once it is, since other handles may share it.
+            match reactor::register_interest(self.fd, reactor::STREAM_INTEREST) {
+                Ok(_) => continue,
+                Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            reactor::register_wake(self.fd, direction, cx.waker().clone())?;
+            return Poll::Pending;
+        }
+    }
+}
+
+impl Drop for NonblockingFd {
+    fn drop(&mut self) {
+        let mut files = NONBLOCKING.lock().unwrap();
+        let Some(i) = files.iter().position(|(file, _, _)| *file == self.file) else {
+            return;
+        };
+        files[i].1 -= 1;
+        if files[i].1 == 0 {
+            let (_, _, flags) = files.swap_remove(i);
+            let _ = sys::set_fd_flags(self.fd, flags);
+        }
+    }
+}
+
+/// Blocking reads and writes on an fd that something else owns.
+struct RawFile(RawFd);
+
+impl Read for RawFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        sys::read(self.0, buf)
+    }
+}
+
+impl Write for RawFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        sys::write(self.0, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::{runtime, syscall};
+
+    /// A pipe in blocking mode, like one a shell would set up.
+    fn blocking_pipe() -> (OwnedFd, OwnedFd) {
+        let mut fds = [0; 2];
+        syscall!(pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC)).unwrap();
+        // SAFETY: `pipe2` just opened these, and nothing else owns them.
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn test_pipe_is_nonblocking_while_in_use() {
+        let (rx, tx) = blocking_pipe();
+        let fd = rx.as_raw_fd();
+        let received = runtime::run(async move {
+            let mut stdin = Stdio::new(fd);
+            assert!(matches!(stdin, Stdio::Nonblocking(_)));
+            let mut other = Stdio::new(fd);
+            assert_ne!(0, sys::get_fd_flags(fd)? & libc::O_NONBLOCK);
+
+            let writer = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                let mut tx = fs::File::from(tx);
+                tx.write_all(b"from another process")
+            });
+
+            // Both handles share the mode, so dropping one leaves it alone.
+            let mut buf = [0; 5];
+            other.read_exact(&mut buf).await?;
+            drop(other);
+            assert_ne!(0, sys::get_fd_flags(fd)? & libc::O_NONBLOCK);
+
+            let mut received = buf.to_vec();
+            stdin.read_to_end(&mut received).await?;
+            writer.join().unwrap()?;
+            io::Result::Ok(received)
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(b"from another process", &received[..]);
+        assert_eq!(0, sys::get_fd_flags(fd).unwrap() & libc::O_NONBLOCK);
+    }
+
+    #[test]
+    fn test_dup_fds_share_the_saved_flags() {
+        let (rx, _tx) = blocking_pipe();
+        let dup = rx.try_clone().unwrap();
+        let (fd, dup_fd) = (rx.as_raw_fd(), dup.as_raw_fd());
+
+        let first = NonblockingFd::new(fd).unwrap();
+        // The dup already sees the first's mode, which mustn't be saved as the
+        // flags to restore.
+        let second = NonblockingFd::new(dup_fd).unwrap();
+        drop(first);
+        assert_ne!(0, sys::get_fd_flags(dup_fd).unwrap() & libc::O_NONBLOCK);
+
+        drop(second);
+        assert_eq!(0, sys::get_fd_flags(fd).unwrap() & libc::O_NONBLOCK);
+        assert_eq!(0, sys::get_fd_flags(dup_fd).unwrap() & libc::O_NONBLOCK);
+    }
+
+    #[test]
+    fn test_regular_files_use_the_blocking_pool() {
+        let path = std::env::temp_dir().join(format!("echo-stdio-{}", std::process::id()));
+        let out = fs::File::create(&path).unwrap();
+        let fd = out.as_raw_fd();
+        runtime::run(async move {
+            let mut stdout = Stdio::new(fd);
+            assert!(matches!(stdout, Stdio::Blocking(_)));
+            stdout.write_all(b"hello ").await?;
+            stdout.write_all(b"world").await?;
+            stdout.flush().await
+        })
+        .unwrap()
+        .unwrap();
+        drop(out);
+
+        let input = fs::File::open(&path).unwrap();
+        let fd = input.as_raw_fd();
+        let read = runtime::run(async move {
+            let mut stdin = Stdio::new(fd);
+            let mut read = Vec::new();
+            stdin.read_to_end(&mut read).await.map(|_| read)
+        })
+        .unwrap()
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(b"hello world", &read[..]);
+    }
+}
@@ -3,15 +3,25 @@ use std::cell::RefCell;
 use self::executor::Executor;
 
 pub mod channel;
+pub mod codec;
+pub mod compat;
 pub mod echo;
 pub mod executor;
+pub mod fs;
+pub mod instrument;
 pub mod io;
 pub mod reactor;
 pub mod runtime;
+pub mod sim;
+pub mod stream;
+pub mod sync;
 pub mod sys;
 pub mod tcp;
+pub mod time;
 
 thread_local! {
     pub(crate) static EXECUTOR: RefCell<Option<Executor>> = RefCell::new(None);
     pub(crate) static REACTOR: RefCell<Option<reactor::Reactor>> = RefCell::new(None);
+    pub(crate) static TIMERS: RefCell<Option<time::Timers>> = const { RefCell::new(None) };
+    pub(crate) static SIMULATION: RefCell<Option<sim::Simulation>> = const { RefCell::new(None) };
 }
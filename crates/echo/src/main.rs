@@ -7,11 +7,9 @@ fn main() {
     runtime::run(async {
         let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
         let listener = TcpListener::bind(addr, 128).unwrap();
-        while let Ok((fd, addr)) = listener.accept().await {
+        while let Ok((stream, addr)) = listener.accept().await {
             println!("Accepted connection from {}", addr);
-            unsafe {
-                libc::close(fd);
-            }
+            drop(stream);
         }
     })
     .unwrap();
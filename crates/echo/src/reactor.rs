@@ -1,19 +1,38 @@
 use std::collections::HashMap;
-use std::io;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::net::SocketAddr;
 use std::os::fd::RawFd;
-use std::task::Waker;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
 
+use crate::executor::{self, Waiting};
+use crate::instrument::{self, Event};
 use crate::{REACTOR, sys, syscall};
 
-pub fn wait_and_wake(events: &mut [libc::epoll_event], timeout: i32) -> io::Result<i32> {
-    REACTOR.with_borrow(|reactor| {
+mod epoll;
+mod uring;
+
+use self::epoll::EpollDriver;
+use self::uring::UringDriver;
+
+/// The most a single completion-based recv or send moves.
+const MAX_OP_BUF: usize = 64 * 1024;
+
+pub fn wait_and_wake(timeout: i32) -> io::Result<usize> {
+    REACTOR.with_borrow_mut(|reactor| {
         let react = reactor
-            .as_ref()
+            .as_mut()
             .expect("Reactor not started on this thread");
-        react.wait_for_events(events, timeout)
+        react.wait_for_events(timeout)
     })
 }
 
+/// Interest for fds read and written as streams, like sockets and pipes. Edge
+/// triggered, as a stream is usually writable and we only want to hear about
+/// it when that changes.
+pub(crate) const STREAM_INTEREST: i32 =
+    libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET;
+
 pub fn register_interest(fd: RawFd, interest: i32) -> io::Result<i32> {
     REACTOR.with_borrow_mut(|reactor| {
         let react = reactor
@@ -23,79 +42,639 @@ pub fn register_interest(fd: RawFd, interest: i32) -> io::Result<i32> {
     })
 }
 
-pub fn register_wake(fd: RawFd, waker: Waker) -> io::Result<()> {
+/// Which kind of readiness a task is waiting for. Each has its own waker, so
+/// one task can read from an fd while another writes to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+pub fn register_wake(fd: RawFd, direction: Direction, waker: Waker) -> io::Result<()> {
+    executor::record_wait(Waiting::Io { fd });
     REACTOR.with_borrow_mut(|reactor| {
         let react = reactor
             .as_mut()
             .expect("Reactor not started on this thread");
-        react.interest_set.insert(fd, Some(waker));
-        Ok(())
+        react.register_wake(fd, direction, waker)
+    })
+}
+
+/// Stops watching `fd`. Does nothing if the reactor has already been torn down,
+/// which happens when IO objects are dropped along with their tasks.
+pub fn deregister(fd: RawFd) -> io::Result<()> {
+    REACTOR.with_borrow_mut(|reactor| match reactor.as_mut() {
+        Some(react) => react.unregister_interest(fd).map(|_| ()),
+        None => Ok(()),
+    })
+}
+
+/// Returns whether `fd` became ready in `direction` while no task was waiting
+/// for it, and forgets that it did.
+pub(crate) fn take_readiness(fd: RawFd, direction: Direction) -> bool {
+    REACTOR.with_borrow_mut(|reactor| {
+        let Some(wakers) = reactor.as_mut().and_then(|r| r.interest_set.get_mut(&fd)) else {
+            return false;
+        };
+        match direction {
+            Direction::Read => std::mem::take(&mut wakers.read_ready),
+            Direction::Write => std::mem::take(&mut wakers.write_ready),
+        }
+    })
+}
+
+/// Whether any task is waiting on IO readiness or an operation that could
+/// still arrive.
+pub(crate) fn has_armed_wakers() -> bool {
+    REACTOR.with_borrow(|reactor| {
+        reactor.as_ref().is_some_and(|react| {
+            react.interest_set.values().any(Wakers::is_armed)
+                || react.ops.values().any(|op| op.waker.is_some())
+        })
+    })
+}
+
+/// The driver behind this thread's reactor, if it has one.
+pub fn driver_kind() -> Option<DriverKind> {
+    REACTOR.with_borrow(|reactor| reactor.as_ref().map(|react| react.driver.kind()))
+}
+
+/// Whether sockets should hand their reads and writes to the reactor with
+/// [`poll_recv`] and friends, rather than waiting for readiness themselves.
+pub(crate) fn completes_io() -> bool {
+    driver_kind() == Some(DriverKind::IoUring)
+}
+
+/// Receives into `bufs` with a completion-based recv. Data that arrives beyond
+/// what `bufs` can hold is kept for the next call.
+pub(crate) fn poll_recv(
+    fd: RawFd,
+    cx: &mut Context<'_>,
+    bufs: &mut [IoSliceMut<'_>],
+) -> Poll<io::Result<usize>> {
+    with_reactor(|react| react.poll_recv(fd, cx, bufs))
+}
+
+/// Sends `bufs` with a completion-based send. The data is copied into the op
+/// when the send starts, so it carries on if the write is abandoned, and how
+/// much it sent is returned by the next call. The caller has to pass the same
+/// data again until then, as with any `poll_write` that returned `Pending`.
+pub(crate) fn poll_send(
+    fd: RawFd,
+    cx: &mut Context<'_>,
+    bufs: &[IoSlice<'_>],
+) -> Poll<io::Result<usize>> {
+    with_reactor(|react| react.poll_send(fd, cx, bufs))
+}
+
+/// Accepts a connection on a listening socket with a completion-based accept.
+/// The new socket is non-blocking, but not registered yet.
+pub(crate) fn poll_accept(
+    fd: RawFd,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<(RawFd, SocketAddr)>> {
+    with_reactor(|react| react.poll_accept(fd, cx))
+}
+
+fn with_reactor<T>(f: impl FnOnce(&mut Reactor) -> T) -> T {
+    REACTOR.with_borrow_mut(|reactor| {
+        f(reactor
+            .as_mut()
+            .expect("Reactor not started on this thread"))
     })
 }
 
+/// Returns a handle that can interrupt this thread's reactor from any thread.
+pub(crate) fn notifier() -> Option<Arc<Notifier>> {
+    REACTOR.with_borrow(|reactor| reactor.as_ref().map(|react| react.notifier.clone()))
+}
+
+/// Wakes a reactor blocked waiting for events, from any thread. Owns its
+/// eventfd, so it stays safe to use from wakers that outlive the reactor.
+pub(crate) struct Notifier {
+    fd: RawFd,
+}
+
+impl Notifier {
+    fn new() -> io::Result<Self> {
+        Ok(Self {
+            fd: sys::eventfd_create()?,
+        })
+    }
+
+    pub(crate) fn notify(&self) {
+        // Can only fail if the counter would overflow, in which case the
+        // reactor already has a wakeup pending.
+        let _ = sys::eventfd_signal(self.fd);
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        let _ = syscall!(close(self.fd));
+    }
+}
+
+/// The tasks waiting on an fd.
+#[derive(Default)]
+struct Wakers {
+    read: Option<Waker>,
+    write: Option<Waker>,
+    /// Readiness that arrived with no task waiting for it. With edge-triggered
+    /// interest there won't be another event until the fd is drained, so
+    /// `AsyncFd` checks these before waiting.
+    read_ready: bool,
+    write_ready: bool,
+}
+
+impl Wakers {
+    fn is_armed(&self) -> bool {
+        self.read.is_some() || self.write.is_some()
+    }
+}
+
+/// Which kernel interface the reactor waits on, chosen with
+/// [`Builder::driver`](crate::runtime::Builder::driver).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DriverKind {
+    /// Readiness-based: sockets wait until they can be read or written, then
+    /// make the syscall themselves.
+    #[default]
+    Epoll,
+    /// Completion-based: socket accepts, reads and writes are submitted to the
+    /// kernel, which reports back once they're done. A write's data is copied
+    /// into its request, which goes ahead even if the write is abandoned; the
+    /// next write on the socket reports what it sent, so should be given the
+    /// same data. Other fds still wait for readiness, with poll requests.
+    /// Falls back to epoll where the kernel doesn't support io_uring, or
+    /// forbids it.
+    IoUring,
+}
+
+/// A source of IO events for the reactor.
+pub(crate) trait Driver {
+    fn kind(&self) -> DriverKind;
+
+    /// Starts watching `fd`, with `interest` as epoll flags. Fails with
+    /// `EEXIST` if it's already watched, and `EPERM` for regular files.
+    fn register(&mut self, fd: RawFd, interest: i32) -> io::Result<()>;
+
+    fn deregister(&mut self, fd: RawFd) -> io::Result<()>;
+
+    /// Asks to hear the next time `fd` is ready in `direction`. Drivers that
+    /// watch registered fds all the time needn't do anything.
+    fn arm(&mut self, fd: RawFd, direction: Direction) -> io::Result<()>;
+
+    /// Starts `op` on `fd`, to be reported as [`DriverEvent::Completed`] with
+    /// `token`. The op's buffers mustn't move or be freed until then.
+    fn submit(&mut self, token: u64, fd: RawFd, op: &mut Op) -> io::Result<()>;
+
+    /// Asks for the op started with `token` to finish early. It's still
+    /// reported as completed, probably with `ECANCELED`.
+    fn cancel(&mut self, token: u64) -> io::Result<()>;
+
+    /// Waits up to `timeout` milliseconds, or forever if it's -1, for at least
+    /// one event, adding them to `events`.
+    fn wait(&mut self, events: &mut Vec<DriverEvent>, timeout: i32) -> io::Result<()>;
+}
+
+pub(crate) enum DriverEvent {
+    /// `fd` is ready, with `events` as epoll flags.
+    Ready { fd: RawFd, events: u32 },
+    /// The op submitted with `token` finished, with the result its syscall
+    /// would have returned, or minus the error number.
+    Completed { token: u64, result: i32 },
+}
+
+/// An operation carried out by a completion-based driver, along with the
+/// memory the kernel reads from or writes to while it runs.
+pub(crate) enum Op {
+    Accept(Box<(libc::sockaddr_storage, libc::socklen_t)>),
+    Recv(Vec<u8>),
+    Send(Vec<u8>),
+}
+
+/// An op that has been submitted, at most one per fd and direction.
+struct InFlight {
+    fd: RawFd,
+    direction: Direction,
+    op: Op,
+    result: Option<i32>,
+    /// How much of a finished recv has been handed out.
+    pos: usize,
+    waker: Option<Waker>,
+}
+
 pub struct Reactor {
-    epoll_fd: RawFd,
-    interest_set: HashMap<RawFd, Option<Waker>>,
+    driver: Box<dyn Driver>,
+    interest_set: HashMap<RawFd, Wakers>,
+    ops: HashMap<u64, InFlight>,
+    /// The op each fd has in flight in each direction. Ops missing from here
+    /// were abandoned when their fd was deregistered, and are dropped as soon
+    /// as they complete.
+    op_tokens: HashMap<(RawFd, Direction), u64>,
+    next_token: u64,
+    events: Vec<DriverEvent>,
+    notifier: Arc<Notifier>,
 }
 
 impl Reactor {
     pub fn new() -> io::Result<Self> {
-        return Ok(Self {
-            epoll_fd: sys::epoll_create()?,
+        Self::with_driver(DriverKind::Epoll)
+    }
+
+    pub fn with_driver(kind: DriverKind) -> io::Result<Self> {
+        let driver: Box<dyn Driver> = match kind {
+            DriverKind::Epoll => Box::new(EpollDriver::new()?),
+            DriverKind::IoUring => match UringDriver::new() {
+                Ok(driver) => Box::new(driver),
+                Err(e) if uring::is_unsupported(&e) => Box::new(EpollDriver::new()?),
+                Err(e) => return Err(e),
+            },
+        };
+
+        let mut reactor = Self {
+            driver,
             interest_set: HashMap::new(),
-        });
+            ops: HashMap::new(),
+            op_tokens: HashMap::new(),
+            next_token: 0,
+            events: Vec::new(),
+            notifier: Arc::new(Notifier::new()?),
+        };
+        // Not part of the interest set: it has no waker and never keeps the
+        // runtime alive on its own.
+        reactor
+            .driver
+            .register(reactor.notifier.fd, libc::EPOLLIN)?;
+        Ok(reactor)
     }
 
     pub fn register_interest(&mut self, fd: RawFd, interest: i32) -> io::Result<i32> {
-        let mut event = libc::epoll_event {
-            events: interest as u32,
-            u64: fd as u64,
+        self.driver.register(fd, interest)?;
+        self.interest_set.insert(fd, Wakers::default());
+        Ok(0)
+    }
+
+    pub fn register_wake(
+        &mut self,
+        fd: RawFd,
+        direction: Direction,
+        waker: Waker,
+    ) -> io::Result<()> {
+        let wakers = self.interest_set.entry(fd).or_default();
+        match direction {
+            Direction::Read => wakers.read = Some(waker),
+            Direction::Write => wakers.write = Some(waker),
+        }
+        self.driver.arm(fd, direction)
+    }
+
+    pub fn unregister_interest(&mut self, fd: RawFd) -> io::Result<i32> {
+        self.interest_set.remove(&fd);
+        // The fd is about to be closed, so stop anything still using it. The
+        // ops' buffers have to outlive them, so they stay until they complete.
+        for direction in [Direction::Read, Direction::Write] {
+            if let Some(token) = self.op_tokens.remove(&(fd, direction)) {
+                let op = self.ops.get_mut(&token).unwrap();
+                op.waker = None;
+                if op.result.is_some() {
+                    let op = self.ops.remove(&token).unwrap();
+                    abandon(op);
+                } else {
+                    self.driver.cancel(token)?;
+                }
+            }
+        }
+        self.driver.deregister(fd)?;
+        Ok(0)
+    }
+
+    pub fn wait_for_events(&mut self, timeout: i32) -> io::Result<usize> {
+        instrument::emit(Event::ReactorWait { timeout });
+        self.driver.arm(self.notifier.fd, Direction::Read)?;
+        let mut events = std::mem::take(&mut self.events);
+        let res = self.driver.wait(&mut events, timeout);
+        let count = events.len();
+        for event in events.drain(..) {
+            match event {
+                DriverEvent::Ready { fd, events } => self.ready(fd, events)?,
+                DriverEvent::Completed { token, result } => self.completed(token, result),
+            }
+        }
+        self.events = events;
+        res.map(|_| count)
+    }
+
+    fn ready(&mut self, fd: RawFd, events: u32) -> io::Result<()> {
+        instrument::emit(Event::ReactorEvent { fd, events });
+        if fd == self.notifier.fd {
+            return sys::eventfd_drain(self.notifier.fd);
+        }
+
+        // Wakers are one-shot: a task that still cares about the fd registers
+        // again the next time it hits `WouldBlock`. Errors and hangups wake
+        // both directions, so each sees the failure.
+        let Some(wakers) = self.interest_set.get_mut(&fd) else {
+            return Ok(());
         };
+        let failed = events & (libc::EPOLLERR | libc::EPOLLHUP) as u32 != 0;
+        let readable = events & (libc::EPOLLIN | libc::EPOLLRDHUP) as u32 != 0;
+        let writable = events & libc::EPOLLOUT as u32 != 0;
+        if readable || failed {
+            match wakers.read.take() {
+                Some(waker) => waker.wake(),
+                None => wakers.read_ready = true,
+            }
+        }
+        if writable || failed {
+            match wakers.write.take() {
+                Some(waker) => waker.wake(),
+                None => wakers.write_ready = true,
+            }
+        }
+        Ok(())
+    }
 
-        let result = syscall!(epoll_ctl(
-            self.epoll_fd,
-            libc::EPOLL_CTL_ADD,
-            fd,
-            &mut event
-        ))?;
+    fn completed(&mut self, token: u64, result: i32) {
+        let Some(op) = self.ops.get_mut(&token) else {
+            return;
+        };
+        if self.op_tokens.get(&(op.fd, op.direction)) != Some(&token) {
+            let mut op = self.ops.remove(&token).unwrap();
+            op.result = Some(result);
+            abandon(op);
+            return;
+        }
+
+        op.result = Some(result);
+        if let Op::Recv(buf) = &mut op.op {
+            buf.truncate(result.max(0) as usize);
+        }
+        if let Some(waker) = op.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Returns the result of the op `fd` has in flight in `direction`, or
+    /// starts one with `start` if there isn't one. Ops that finished are
+    /// forgotten unless `keep` says there's more to hand out.
+    fn poll_op<T>(
+        &mut self,
+        fd: RawFd,
+        direction: Direction,
+        cx: &mut Context<'_>,
+        start: impl FnOnce() -> Option<Op>,
+        finish: impl FnOnce(&mut InFlight, i32) -> (io::Result<T>, bool),
+        idle: impl FnOnce() -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        let key = (fd, direction);
+        if let Some(&token) = self.op_tokens.get(&key) {
+            let op = self.ops.get_mut(&token).unwrap();
+            let Some(result) = op.result else {
+                op.waker = Some(cx.waker().clone());
+                executor::record_wait(Waiting::Io { fd });
+                return Poll::Pending;
+            };
+            let (res, keep) = if result < 0 {
+                (Err(io::Error::from_raw_os_error(-result)), false)
+            } else {
+                finish(op, result)
+            };
+            if !keep {
+                self.op_tokens.remove(&key);
+                self.ops.remove(&token);
+            }
+            return Poll::Ready(res);
+        }
 
-        self.interest_set.insert(fd, None);
-        Ok(result)
+        let Some(mut op) = start() else {
+            return Poll::Ready(idle());
+        };
+        let token = self.next_token;
+        self.next_token += 1;
+        self.driver.submit(token, fd, &mut op)?;
+        self.ops.insert(
+            token,
+            InFlight {
+                fd,
+                direction,
+                op,
+                result: None,
+                pos: 0,
+                waker: Some(cx.waker().clone()),
+            },
+        );
+        self.op_tokens.insert(key, token);
+        executor::record_wait(Waiting::Io { fd });
+        Poll::Pending
     }
 
-    pub fn register_wake(&mut self, fd: RawFd, waker: Waker) {
-        // TODO: Is it realistic for multiple wakers to be registered for the same fd?
-        self.interest_set.insert(fd, Some(waker));
+    fn poll_recv(
+        &mut self,
+        fd: RawFd,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let len: usize = bufs.iter().map(|b| b.len()).sum();
+        self.poll_op(
+            fd,
+            Direction::Read,
+            cx,
+            || (len > 0).then(|| Op::Recv(vec![0; len.min(MAX_OP_BUF)])),
+            |op, _| {
+                let Op::Recv(data) = &op.op else {
+                    unreachable!("recv finished with another op's buffer");
+                };
+                let mut copied = 0;
+                for buf in bufs.iter_mut() {
+                    let src = &data[op.pos + copied..];
+                    let n = src.len().min(buf.len());
+                    buf[..n].copy_from_slice(&src[..n]);
+                    copied += n;
+                }
+                op.pos += copied;
+                (Ok(copied), op.pos < data.len())
+            },
+            || Ok(0),
+        )
     }
 
-    // TODO: Make sure we do this on drop of any io stuff
-    pub fn unregister_interest(&self, fd: RawFd) -> io::Result<i32> {
-        syscall!(epoll_ctl(
-            self.epoll_fd,
-            libc::EPOLL_CTL_DEL,
+    fn poll_send(
+        &mut self,
+        fd: RawFd,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_op(
             fd,
-            std::ptr::null_mut()
-        ))
-    }
-
-    pub fn wait_for_events(
-        &self,
-        events: &mut [libc::epoll_event],
-        timeout: i32,
-    ) -> io::Result<i32> {
-        let res = syscall!(epoll_wait(
-            self.epoll_fd,
-            events.as_mut_ptr(),
-            events.len() as i32,
-            timeout
-        ))?;
-
-        for event in &events[0..res as usize] {
-            if let Some(Some(waker)) = self.interest_set.get(&(event.u64 as RawFd)) {
-                waker.wake_by_ref();
+            Direction::Write,
+            cx,
+            || {
+                let mut data = Vec::new();
+                for buf in bufs {
+                    let n = buf.len().min(MAX_OP_BUF - data.len());
+                    data.extend_from_slice(&buf[..n]);
+                }
+                (!data.is_empty()).then_some(Op::Send(data))
+            },
+            |_, sent| (Ok(sent as usize), false),
+            || Ok(0),
+        )
+    }
+
+    fn poll_accept(
+        &mut self,
+        fd: RawFd,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(RawFd, SocketAddr)>> {
+        self.poll_op(
+            fd,
+            Direction::Read,
+            cx,
+            || {
+                // SAFETY: all zeroes is a valid `sockaddr_storage`.
+                let storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+                let len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                Some(Op::Accept(Box::new((storage, len))))
+            },
+            |op, new_fd| {
+                let Op::Accept(addr) = &op.op else {
+                    unreachable!("accept finished with another op's buffer");
+                };
+                let res = sys::storage_to_socketaddr(&addr.0).map(|addr| (new_fd, addr));
+                if res.is_err() {
+                    let _ = sys::close_socket(new_fd);
+                }
+                (res, false)
+            },
+            || unreachable!("accept always starts an op"),
+        )
+    }
+}
+
+/// Cleans up after an op nobody is waiting for any more.
+fn abandon(op: InFlight) {
+    if let (Op::Accept(_), Some(new_fd)) = (&op.op, op.result)
+        && new_fd >= 0
+    {
+        let _ = sys::close_socket(new_fd);
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        // The kernel can write to an op's buffers until it completes, so wait
+        // for everything in flight to be cancelled before freeing them.
+        let fds: Vec<_> = self.op_tokens.keys().map(|(fd, _)| *fd).collect();
+        for fd in fds {
+            let _ = self.unregister_interest(fd);
+        }
+        for _ in 0..100 {
+            if self.ops.is_empty() || self.wait_for_events(10).is_err() {
+                break;
             }
         }
+        // Leaking is the only safe option if the kernel never answers.
+        if !self.ops.is_empty() {
+            std::mem::forget(std::mem::take(&mut self.ops));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::poll_fn;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::pin::Pin;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::io::{AsyncFd, AsyncRead, AsyncWriteExt};
+    use crate::runtime::Builder;
+    use crate::tcp::{TcpListener, TcpStream};
+    use crate::time;
+
+    fn io_uring() -> Builder {
+        Builder::new().driver(DriverKind::IoUring)
+    }
+
+    #[test]
+    fn test_io_uring_falls_back_to_epoll() {
+        let expected = match UringDriver::new() {
+            Ok(_) => DriverKind::IoUring,
+            Err(e) => {
+                assert!(uring::is_unsupported(&e), "{e}");
+                DriverKind::Epoll
+            }
+        };
+        assert_eq!(
+            Some(expected),
+            io_uring().run(async { driver_kind() }).unwrap()
+        );
+        assert_eq!(
+            Some(DriverKind::Epoll),
+            Builder::new().run(async { driver_kind() }).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_io_uring_timers_and_readiness() {
+        let (received, elapsed) = io_uring()
+            .run(async {
+                let start = Instant::now();
+                time::sleep(Duration::from_millis(20)).await;
+                let elapsed = start.elapsed();
+
+                let (rx, tx) = sys::pipe()?;
+                // SAFETY: `pipe` just opened these, and nothing else owns them.
+                let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(rx), OwnedFd::from_raw_fd(tx)) };
+                let rx = AsyncFd::new(rx)?;
+                let writer = thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    sys::write(tx.as_raw_fd(), b"ping")
+                });
+
+                let mut buf = [0; 16];
+                let n = loop {
+                    let mut guard = rx.readable().await?;
+                    if let Ok(res) = guard.try_io(|fd| sys::read(fd.as_raw_fd(), &mut buf)) {
+                        break res?;
+                    }
+                };
+                writer.join().unwrap()?;
+                io::Result::Ok((buf[..n].to_vec(), elapsed))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(b"ping", &received[..]);
+        assert!(elapsed >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_dropping_a_stream_cancels_its_ops() {
+        let in_flight = io_uring()
+            .run(async {
+                let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+                let mut client = TcpStream::connect(listener.local_addr()?).await?;
+                let (mut server, _) = listener.accept().await?;
+
+                // Starts a recv that nothing will complete.
+                let mut buf = [0; 16];
+                let started = poll_fn(|cx| {
+                    Poll::Ready(Pin::new(&mut server).poll_read(cx, &mut buf).is_pending())
+                })
+                .await;
+                drop(server);
+                time::sleep(Duration::from_millis(10)).await;
 
-        Ok(res)
+                let _ = client.write_all(b"too late").await;
+                let in_flight = REACTOR.with_borrow(|r| r.as_ref().unwrap().ops.len());
+                io::Result::Ok(started.then_some(in_flight))
+            })
+            .unwrap()
+            .unwrap();
+        assert!(matches!(in_flight, Some(0) | None));
     }
 }
@@ -0,0 +1,88 @@
+use std::io;
+use std::os::fd::RawFd;
+
+use super::{Direction, Driver, DriverEvent, DriverKind, Op};
+use crate::{sys, syscall};
+
+/// Waits on an epoll instance. Fds stay registered with whatever interest they
+/// were given, so there's nothing to arm, and operations aren't supported.
+pub(super) struct EpollDriver {
+    epoll_fd: RawFd,
+    events: Vec<libc::epoll_event>,
+}
+
+impl EpollDriver {
+    pub(super) fn new() -> io::Result<Self> {
+        Ok(Self {
+            epoll_fd: sys::epoll_create()?,
+            events: vec![libc::epoll_event { events: 0, u64: 0 }; 128],
+        })
+    }
+}
+
+impl Driver for EpollDriver {
+    fn kind(&self) -> DriverKind {
+        DriverKind::Epoll
+    }
+
+    fn register(&mut self, fd: RawFd, interest: i32) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: interest as u32,
+            u64: fd as u64,
+        };
+        syscall!(epoll_ctl(
+            self.epoll_fd,
+            libc::EPOLL_CTL_ADD,
+            fd,
+            &mut event
+        ))
+        .map(|_| ())
+    }
+
+    fn deregister(&mut self, fd: RawFd) -> io::Result<()> {
+        syscall!(epoll_ctl(
+            self.epoll_fd,
+            libc::EPOLL_CTL_DEL,
+            fd,
+            std::ptr::null_mut()
+        ))
+        .map(|_| ())
+    }
+
+    fn arm(&mut self, _fd: RawFd, _direction: Direction) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn submit(&mut self, _token: u64, _fd: RawFd, _op: &mut Op) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn cancel(&mut self, _token: u64) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn wait(&mut self, events: &mut Vec<DriverEvent>, timeout: i32) -> io::Result<()> {
+        let res = syscall!(epoll_wait(
+            self.epoll_fd,
+            self.events.as_mut_ptr(),
+            self.events.len() as i32,
+            timeout
+        ))?;
+
+        events.extend(
+            self.events[..res as usize]
+                .iter()
+                .map(|event| DriverEvent::Ready {
+                    fd: event.u64 as RawFd,
+                    events: event.events,
+                }),
+        );
+        Ok(())
+    }
+}
+
+impl Drop for EpollDriver {
+    fn drop(&mut self) {
+        let _ = syscall!(close(self.epoll_fd));
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::os::fd::RawFd;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::{Direction, Driver, DriverEvent, DriverKind, Op};
+use crate::{sys, syscall};
+
+// From `linux/io_uring.h`, which libc doesn't cover. The syscall numbers are
+// the same on every architecture but alpha and mips.
+const SYS_IO_URING_SETUP: libc::c_long = 425;
+const SYS_IO_URING_ENTER: libc::c_long = 426;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+const IORING_FEAT_NODROP: u32 = 1 << 1;
+/// Came last of everything used here, in 5.7, so implies the rest.
+const IORING_FEAT_FAST_POLL: u32 = 1 << 5;
+
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_POLL_REMOVE: u8 = 7;
+const IORING_OP_TIMEOUT: u8 = 11;
+const IORING_OP_TIMEOUT_REMOVE: u8 = 12;
+const IORING_OP_ACCEPT: u8 = 13;
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+const IORING_OP_SEND: u8 = 26;
+const IORING_OP_RECV: u8 = 27;
+
+/// How many submissions fit in the ring before it has to be flushed.
+const ENTRIES: u32 = 256;
+
+/// The user data of requests whose completions don't matter, like removals.
+const IGNORED: u64 = u64::MAX;
+/// Set in the user data of the driver's own requests, and never in the tokens
+/// of the reactor's ops.
+const INTERNAL: u64 = 1 << 63;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// A submission, with the kernel's unions named after the member used here.
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+const _: () = assert!(size_of::<Params>() == 120);
+const _: () = assert!(size_of::<Sqe>() == 64);
+const _: () = assert!(size_of::<Cqe>() == 16);
+
+/// Whether `e`, from [`UringDriver::new`], means io_uring can't be used here,
+/// because the kernel is too old or it's been turned off.
+pub(super) fn is_unsupported(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Unsupported
+        || matches!(
+            e.raw_os_error(),
+            Some(libc::ENOSYS | libc::EPERM | libc::EACCES | libc::EINVAL)
+        )
+}
+
+/// Memory shared with the kernel.
+struct Mmap {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping, which nothing else refers to.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).unwrap(),
+            len,
+        })
+    }
+
+    /// Points `offset` bytes in, at one of the fields the kernel told us about.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        // SAFETY: the kernel's offsets are within the mapping.
+        unsafe { self.ptr.as_ptr().add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: mapped in `new`, and only ever pointed into by the driver
+        // that owns it.
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+    }
+}
+
+/// Waits on an io_uring instance, driven with raw syscalls.
+///
+/// The reactor's ops are submitted as they are, and readiness is waited for
+/// with one-shot poll requests, armed when a task starts waiting. Timeouts are
+/// submitted as requests too, so one `io_uring_enter` both submits everything
+/// queued since the last wait and blocks for completions.
+pub(super) struct UringDriver {
+    fd: RawFd,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    /// Fds that act as registered, for the same errors as epoll.
+    registered: HashSet<RawFd>,
+    /// What each poll request in flight is for. Requests missing from here
+    /// have been removed, and their completions are ignored.
+    polls: HashMap<u64, (RawFd, Direction)>,
+    armed: HashMap<(RawFd, Direction), u64>,
+    /// The timeout from the last wait, if it may not have fired yet.
+    timeout: Option<u64>,
+    /// What the timeout request points to. Boxed so it stays put until the
+    /// kernel has read it, however the submission gets made.
+    duration: Box<Timespec>,
+    next_token: u64,
+    // Unmapped after `fd` is closed, which is fine: the kernel keeps the
+    // rings alive until both are gone.
+    _rings: Mmap,
+    _sqes: Mmap,
+}
+
+impl UringDriver {
+    pub(super) fn new() -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: `params` is valid for the kernel to fill in.
+        let fd = unsafe { libc::syscall(SYS_IO_URING_SETUP, ENTRIES, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as RawFd;
+
+        let maps = Self::map(fd, &params);
+        let (rings, sqes) = match maps {
+            Ok(maps) => maps,
+            Err(e) => {
+                let _ = syscall!(close(fd));
+                return Err(e);
+            }
+        };
+
+        // Submissions go in the same slot of the array as of the entries, so
+        // the array never changes.
+        let array: *mut u32 = rings.at(params.sq_off.array);
+        for i in 0..params.sq_entries {
+            // SAFETY: the array has room for every entry.
+            unsafe { array.add(i as usize).write(i) };
+        }
+
+        // SAFETY: the masks are written by the kernel during setup, and
+        // never change.
+        let (sq_mask, cq_mask) = unsafe {
+            (
+                *rings.at::<u32>(params.sq_off.ring_mask),
+                *rings.at::<u32>(params.cq_off.ring_mask),
+            )
+        };
+        Ok(Self {
+            fd,
+            sq_head: rings.at(params.sq_off.head),
+            sq_tail: rings.at(params.sq_off.tail),
+            sq_mask,
+            sq_entries: params.sq_entries,
+            sqes: sqes.at(0),
+            cq_head: rings.at(params.cq_off.head),
+            cq_tail: rings.at(params.cq_off.tail),
+            cq_mask,
+            cqes: rings.at(params.cq_off.cqes),
+            registered: HashSet::new(),
+            polls: HashMap::new(),
+            armed: HashMap::new(),
+            timeout: None,
+            duration: Box::new(Timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            }),
+            next_token: 0,
+            _rings: rings,
+            _sqes: sqes,
+        })
+    }
+
+    fn map(fd: RawFd, params: &Params) -> io::Result<(Mmap, Mmap)> {
+        let required = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_NODROP | IORING_FEAT_FAST_POLL;
+        if params.features & required != required {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let rings = Mmap::new(fd, sq_len.max(cq_len), IORING_OFF_SQ_RING)?;
+        let sqes = Mmap::new(
+            fd,
+            params.sq_entries as usize * size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        Ok((rings, sqes))
+    }
+
+    fn atomic(&self, ptr: *const AtomicU32) -> &AtomicU32 {
+        // SAFETY: points into the rings, which live as long as `self`.
+        unsafe { &*ptr }
+    }
+
+    fn token(&mut self) -> u64 {
+        self.next_token += 1;
+        INTERNAL | self.next_token
+    }
+
+    /// Queues a submission, to go to the kernel on the next wait.
+    fn push(&mut self, sqe: Sqe) -> io::Result<()> {
+        let tail = self.atomic(self.sq_tail).load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.atomic(self.sq_head).load(Ordering::Acquire)) == self.sq_entries {
+            self.enter(0, 0)?;
+            if tail.wrapping_sub(self.atomic(self.sq_head).load(Ordering::Acquire))
+                == self.sq_entries
+            {
+                return Err(io::Error::other("io_uring submission queue is full"));
+            }
+        }
+
+        // SAFETY: the slot is within the entries, and the kernel is done with
+        // it as the head has moved past it.
+        unsafe { self.sqes.add((tail & self.sq_mask) as usize).write(sqe) };
+        self.atomic(self.sq_tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Submits everything queued, then waits for `min_complete` completions.
+    fn enter(&mut self, min_complete: u32, flags: u32) -> io::Result<()> {
+        let queued = self
+            .atomic(self.sq_tail)
+            .load(Ordering::Relaxed)
+            .wrapping_sub(self.atomic(self.sq_head).load(Ordering::Acquire));
+        // SAFETY: no signal mask is passed, and everything queued points at
+        // memory that outlives the request.
+        let res = unsafe {
+            libc::syscall(
+                SYS_IO_URING_ENTER,
+                self.fd,
+                queued,
+                min_complete,
+                flags,
+                ptr::null::<libc::sigset_t>(),
+                0usize,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn has_completions(&self) -> bool {
+        self.atomic(self.cq_tail).load(Ordering::Acquire)
+            != self.atomic(self.cq_head).load(Ordering::Relaxed)
+    }
+
+    fn reap(&mut self, events: &mut Vec<DriverEvent>) {
+        let mut head = self.atomic(self.cq_head).load(Ordering::Relaxed);
+        let tail = self.atomic(self.cq_tail).load(Ordering::Acquire);
+        while head != tail {
+            // SAFETY: entries between the head and tail are filled in, and
+            // ours until the head moves past them.
+            let cqe = unsafe { &*self.cqes.add((head & self.cq_mask) as usize) };
+            let (user_data, res) = (cqe.user_data, cqe.res);
+            head = head.wrapping_add(1);
+            self.complete(user_data, res, events);
+        }
+        self.atomic(self.cq_head).store(head, Ordering::Release);
+    }
+
+    fn complete(&mut self, user_data: u64, res: i32, events: &mut Vec<DriverEvent>) {
+        if user_data == IGNORED {
+            return;
+        }
+        if user_data & INTERNAL == 0 {
+            events.push(DriverEvent::Completed {
+                token: user_data,
+                result: res,
+            });
+            return;
+        }
+        if self.timeout == Some(user_data) {
+            self.timeout = None;
+            return;
+        }
+
+        let Some((fd, direction)) = self.polls.remove(&user_data) else {
+            return;
+        };
+        if self.armed.get(&(fd, direction)) == Some(&user_data) {
+            self.armed.remove(&(fd, direction));
+        }
+        // Poll flags have the same values as epoll's.
+        let ready = match res {
+            0.. => res as u32,
+            _ if res == -libc::ECANCELED => return,
+            _ => libc::EPOLLERR as u32,
+        };
+        events.push(DriverEvent::Ready { fd, events: ready });
+    }
+}
+
+impl Driver for UringDriver {
+    fn kind(&self) -> DriverKind {
+        DriverKind::IoUring
+    }
+
+    fn register(&mut self, fd: RawFd, _interest: i32) -> io::Result<()> {
+        if sys::is_regular_file(fd)? {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+        if !self.registered.insert(fd) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
+        }
+        Ok(())
+    }
+
+    fn deregister(&mut self, fd: RawFd) -> io::Result<()> {
+        for direction in [Direction::Read, Direction::Write] {
+            if let Some(token) = self.armed.remove(&(fd, direction)) {
+                self.polls.remove(&token);
+                self.push(Sqe {
+                    opcode: IORING_OP_POLL_REMOVE,
+                    addr: token,
+                    user_data: IGNORED,
+                    ..Sqe::default()
+                })?;
+            }
+        }
+        // The fd is usually closed next, after which its number can be reused.
+        // Hand over everything queued for it while it still means this file.
+        self.enter(0, 0)?;
+
+        if !self.registered.remove(&fd) {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        Ok(())
+    }
+
+    fn arm(&mut self, fd: RawFd, direction: Direction) -> io::Result<()> {
+        if self.armed.contains_key(&(fd, direction)) {
+            return Ok(());
+        }
+        let mask = match direction {
+            Direction::Read => libc::EPOLLIN | libc::EPOLLRDHUP,
+            Direction::Write => libc::EPOLLOUT,
+        };
+        let token = self.token();
+        self.push(Sqe {
+            opcode: IORING_OP_POLL_ADD,
+            fd,
+            op_flags: mask as u32,
+            user_data: token,
+            ..Sqe::default()
+        })?;
+        self.polls.insert(token, (fd, direction));
+        self.armed.insert((fd, direction), token);
+        Ok(())
+    }
+
+    fn submit(&mut self, token: u64, fd: RawFd, op: &mut Op) -> io::Result<()> {
+        debug_assert_eq!(0, token & INTERNAL);
+        let sqe = match op {
+            Op::Accept(addr) => Sqe {
+                opcode: IORING_OP_ACCEPT,
+                fd,
+                addr: &mut addr.0 as *mut libc::sockaddr_storage as u64,
+                off: &mut addr.1 as *mut libc::socklen_t as u64,
+                op_flags: (libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC) as u32,
+                user_data: token,
+                ..Sqe::default()
+            },
+            Op::Recv(buf) => Sqe {
+                opcode: IORING_OP_RECV,
+                fd,
+                addr: buf.as_mut_ptr() as u64,
+                len: buf.len() as u32,
+                user_data: token,
+                ..Sqe::default()
+            },
+            Op::Send(buf) => Sqe {
+                opcode: IORING_OP_SEND,
+                fd,
+                addr: buf.as_ptr() as u64,
+                len: buf.len() as u32,
+                op_flags: libc::MSG_NOSIGNAL as u32,
+                user_data: token,
+                ..Sqe::default()
+            },
+        };
+        self.push(sqe)
+    }
+
+    fn cancel(&mut self, token: u64) -> io::Result<()> {
+        self.push(Sqe {
+            opcode: IORING_OP_ASYNC_CANCEL,
+            addr: token,
+            user_data: IGNORED,
+            ..Sqe::default()
+        })
+    }
+
+    fn wait(&mut self, events: &mut Vec<DriverEvent>, timeout: i32) -> io::Result<()> {
+        // A timeout left over from a wait that ended early would cut the next
+        // one short.
+        if let Some(token) = self.timeout.take() {
+            self.push(Sqe {
+                opcode: IORING_OP_TIMEOUT_REMOVE,
+                addr: token,
+                user_data: IGNORED,
+                ..Sqe::default()
+            })?;
+        }
+
+        let min_complete = if timeout == 0 || self.has_completions() {
+            0
+        } else {
+            if timeout > 0 {
+                *self.duration = Timespec {
+                    tv_sec: timeout as i64 / 1000,
+                    tv_nsec: timeout as i64 % 1000 * 1_000_000,
+                };
+                let token = self.token();
+                self.push(Sqe {
+                    opcode: IORING_OP_TIMEOUT,
+                    fd: -1,
+                    addr: &*self.duration as *const Timespec as u64,
+                    len: 1,
+                    user_data: token,
+                    ..Sqe::default()
+                })?;
+                self.timeout = Some(token);
+            }
+            1
+        };
+
+        match self.enter(min_complete, IORING_ENTER_GETEVENTS) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            res => res?,
+        }
+        self.reap(events);
+        Ok(())
+    }
+}
+
+impl Drop for UringDriver {
+    fn drop(&mut self) {
+        let _ = syscall!(close(self.fd));
+    }
+}
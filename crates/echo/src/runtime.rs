@@ -1,34 +1,325 @@
+use std::fmt::Display;
 use std::io;
+use std::time::Instant;
 
-use crate::executor::{self, Executor};
-use crate::{EXECUTOR, REACTOR, reactor};
+use crate::executor::{self, Executor, TaskId, Waiting};
+use crate::reactor::DriverKind;
+use crate::sim::net::NetConfig;
+use crate::time::{self, Clock, Timers};
+use crate::{EXECUTOR, REACTOR, SIMULATION, TIMERS, reactor, sim};
 
+/// Runs `fut` to completion on a new runtime on the current thread.
 pub fn run<F, T>(fut: F) -> io::Result<T>
 where
     F: Future<Output = T> + 'static,
     T: 'static,
 {
-    REACTOR.with(|reactor| {
-        if reactor.borrow().is_some() {
-            panic!("Reactor already started on this thread");
+    Builder::new().run(fut)
+}
+
+#[derive(Default)]
+pub struct Builder {
+    seed: Option<u64>,
+    network: NetConfig,
+    detect_deadlocks: bool,
+    driver: DriverKind,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A runtime whose behaviour is fully determined by `seed`. Ready tasks are
+    /// polled in a seeded random order, time is virtual and only advances once
+    /// every task is idle, and no real IO is performed.
+    pub fn simulation(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..Self::default()
         }
-        // TODO: Error handling
-        let react = reactor::Reactor::new().unwrap();
-        *reactor.borrow_mut() = Some(react);
-    });
+    }
 
-    EXECUTOR.with(|exec| {
-        if exec.borrow().is_some() {
+    /// Configures the in-memory network that replaces real sockets under a
+    /// simulation, e.g. to inject latency and faults. Has no effect otherwise.
+    pub fn network(mut self, config: NetConfig) -> Self {
+        self.network = config;
+        self
+    }
+
+    /// A debugging aid for lost wakeups. When every task is waiting and nothing
+    /// is left that could wake one (no timers, no IO wakers and nothing waiting
+    /// on another thread), `run` returns an error of kind `Deadlock` wrapping a
+    /// [`Deadlock`] report instead of blocking forever. Also records what each
+    /// task waits on, which costs a little on every `Pending`.
+    ///
+    /// Simulations always stop when they stall, but only include what tasks
+    /// were waiting on in the report if this is enabled. They can't see wakes
+    /// from other threads, so shouldn't be used with `channel::mt`.
+    pub fn detect_deadlocks(mut self, enabled: bool) -> Self {
+        self.detect_deadlocks = enabled;
+        self
+    }
+
+    /// Chooses what the reactor waits on for IO. Defaults to epoll. Has no
+    /// effect under a simulation, which does no real IO.
+    pub fn driver(mut self, kind: DriverKind) -> Self {
+        self.driver = kind;
+        self
+    }
+
+    pub fn run<F, T>(self, fut: F) -> io::Result<T>
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        if EXECUTOR.with_borrow(|exec| exec.is_some()) {
             panic!("Runtime already started on this thread");
         }
-        let executor = Executor::new();
-        return *exec.borrow_mut() = Some(executor);
-    });
-
-    let _ = executor::spawn(fut);
-    loop {
-        executor::make_progress();
-        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 128];
-        reactor::wait_and_wake(&mut events, -1)?;
+
+        let _guard = RuntimeGuard;
+        let clock = match self.seed {
+            Some(seed) => {
+                SIMULATION.set(Some(sim::Simulation::new(seed, self.network)));
+                Clock::Virtual(Instant::now())
+            }
+            None => {
+                REACTOR.set(Some(reactor::Reactor::with_driver(self.driver)?));
+                Clock::Real
+            }
+        };
+
+        TIMERS.set(Some(Timers::new(clock)));
+        let mut executor = Executor::new();
+        executor.track_waits(self.detect_deadlocks);
+        if let Some(notifier) = reactor::notifier() {
+            executor.set_notifier(notifier);
+        }
+        EXECUTOR.set(Some(executor));
+
+        let handle = executor::spawn(fut);
+        loop {
+            executor::make_progress();
+            if let Some(output) = handle.try_take() {
+                return Ok(output);
+            }
+
+            if self.seed.is_some() {
+                if !time::advance_to_next_deadline() {
+                    return Err(Deadlock::report());
+                }
+                continue;
+            }
+
+            if self.detect_deadlocks
+                && time::next_deadline().is_none()
+                && !reactor::has_armed_wakers()
+                && !executor::has_remote_waits()
+            {
+                return Err(Deadlock::report());
+            }
+
+            reactor::wait_and_wake(time::epoll_timeout())?;
+            time::fire_expired();
+        }
+    }
+}
+
+/// Every task was waiting with nothing left that could wake it. Returned from
+/// [`Builder::run`] inside an `io::Error` of kind `Deadlock`.
+#[derive(Debug)]
+pub struct Deadlock {
+    pub tasks: Vec<StuckTask>,
+}
+
+#[derive(Debug)]
+pub struct StuckTask {
+    pub id: TaskId,
+    /// What the task last waited on, if the runtime was recording it.
+    pub waiting: Option<Waiting>,
+}
+
+impl Deadlock {
+    fn report() -> io::Error {
+        let tasks = executor::pending_tasks()
+            .into_iter()
+            .map(|(id, waiting)| StuckTask { id, waiting })
+            .collect();
+        io::Error::new(io::ErrorKind::Deadlock, Deadlock { tasks })
+    }
+}
+
+impl Display for Deadlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "deadlock: {} task(s) waiting with nothing left to wake them",
+            self.tasks.len()
+        )?;
+        for task in &self.tasks {
+            match &task.waiting {
+                Some(waiting) => write!(f, "\n  task {} waiting on {}", task.id, waiting)?,
+                None => write!(f, "\n  task {} waiting on unknown", task.id)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Deadlock {}
+
+/// Tears down the thread's runtime state when `run` returns or unwinds, so a
+/// new runtime can be started on the same thread afterwards.
+struct RuntimeGuard;
+
+impl Drop for RuntimeGuard {
+    fn drop(&mut self) {
+        // Remaining tasks may touch the timers or reactor as they are dropped,
+        // so the executor has to go first.
+        drop(EXECUTOR.take());
+        drop(TIMERS.take());
+        drop(REACTOR.take());
+        drop(SIMULATION.take());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn interleaving(seed: u64) -> Vec<usize> {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let log = order.clone();
+        Builder::simulation(seed)
+            .run(async move {
+                let handles: Vec<_> = (0..8)
+                    .map(|i| {
+                        let log = log.clone();
+                        executor::spawn(async move { log.borrow_mut().push(i) })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            })
+            .unwrap();
+        order.take()
+    }
+
+    #[test]
+    fn test_run_returns_output() {
+        assert_eq!(42, run(async { 42 }).unwrap());
+    }
+
+    #[test]
+    fn test_run_sleep() {
+        let elapsed = run(async {
+            let start = time::now();
+            time::sleep(Duration::from_millis(10)).await;
+            start.elapsed()
+        })
+        .unwrap();
+        assert!(elapsed >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_simulation_is_deterministic() {
+        assert_eq!(interleaving(1), interleaving(1));
+        assert!((2..10).any(|seed| interleaving(seed) != interleaving(1)));
+    }
+
+    #[test]
+    fn test_simulation_virtual_time() {
+        let (elapsed, real) = Builder::simulation(0)
+            .run(async {
+                let real = Instant::now();
+                let start = time::now();
+                time::sleep(Duration::from_secs(3600)).await;
+                (time::now() - start, real.elapsed())
+            })
+            .unwrap();
+        assert_eq!(Duration::from_secs(3600), elapsed);
+        assert!(real < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_simulation_timers_fire_in_order() {
+        let order = Builder::simulation(3)
+            .run(async {
+                let order = Rc::new(RefCell::new(Vec::new()));
+                let handles: Vec<_> = [30, 10, 20]
+                    .into_iter()
+                    .map(|ms| {
+                        let order = order.clone();
+                        executor::spawn(async move {
+                            time::sleep(Duration::from_millis(ms)).await;
+                            order.borrow_mut().push(ms);
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+                order.take()
+            })
+            .unwrap();
+        assert_eq!(vec![10, 20, 30], order);
+    }
+
+    #[test]
+    fn test_detect_deadlock() {
+        let err = Builder::new()
+            .detect_deadlocks(true)
+            .run(async {
+                let (tx, rx) = crate::channel::oneshot::<()>();
+                let (_never, blocked) = crate::channel::oneshot::<()>();
+                let _child = executor::spawn(async move {
+                    let _ = blocked.await;
+                    tx.send(()).unwrap();
+                });
+                let _ = rx.await;
+            })
+            .unwrap_err();
+
+        assert_eq!(io::ErrorKind::Deadlock, err.kind());
+        let report = err.get_ref().unwrap().downcast_ref::<Deadlock>().unwrap();
+        let receiver = Waiting::Resource(std::any::type_name::<crate::channel::Receiver<()>>());
+        assert_eq!(2, report.tasks.len());
+        assert_eq!(
+            (0, Some(&receiver)),
+            (report.tasks[0].id, report.tasks[0].waiting.as_ref())
+        );
+        assert_eq!(
+            (1, Some(&receiver)),
+            (report.tasks[1].id, report.tasks[1].waiting.as_ref())
+        );
+    }
+
+    #[test]
+    fn test_detect_deadlock_waits_for_timers() {
+        let result = Builder::new()
+            .detect_deadlocks(true)
+            .run(async {
+                let handle = executor::spawn(time::sleep(Duration::from_millis(5)));
+                handle.await.unwrap();
+                7
+            })
+            .unwrap();
+        assert_eq!(7, result);
+    }
+
+    #[test]
+    fn test_simulation_stall() {
+        let err = Builder::simulation(0)
+            .run(async {
+                let (_tx, rx) = crate::channel::oneshot::<()>();
+                let _ = rx.await;
+            })
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::Deadlock, err.kind());
     }
 }
@@ -0,0 +1,108 @@
+//! Support for running the runtime deterministically.
+//!
+//! A simulation is started with [`crate::runtime::Builder::simulation`]. All of
+//! the nondeterminism the runtime would normally get from the OS (scheduling
+//! order, the passage of time, network behaviour) is instead derived from a
+//! single seeded random number generator, so a run can be replayed exactly by
+//! reusing its seed.
+
+use crate::SIMULATION;
+
+use self::net::{NetConfig, Network};
+
+pub mod net;
+
+pub(crate) struct Simulation {
+    seed: u64,
+    rng: Rng,
+    network: Network,
+}
+
+impl Simulation {
+    pub(crate) fn new(seed: u64, config: NetConfig) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed),
+            network: Network::new(config),
+        }
+    }
+}
+
+/// Returns the seed of the simulation running on this thread, if any.
+pub fn seed() -> Option<u64> {
+    SIMULATION.with_borrow(|sim| sim.as_ref().map(|sim| sim.seed))
+}
+
+pub(crate) fn is_enabled() -> bool {
+    SIMULATION.with_borrow(|sim| sim.is_some())
+}
+
+/// Picks an index in `0..len` when simulating, so callers can randomize an
+/// otherwise fixed order. Returns `None` outside of a simulation.
+pub(crate) fn choose(len: usize) -> Option<usize> {
+    SIMULATION.with_borrow_mut(|sim| {
+        let sim = sim.as_mut()?;
+        Some(sim.rng.below(len as u64) as usize)
+    })
+}
+
+/// A small, fast PRNG (splitmix64). Not suitable for anything but reproducible
+/// testing.
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in `0.0..1.0`.
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns true with probability `p`.
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.unit() < p
+    }
+
+    /// Returns a value in `0..n`, or 0 when `n` is 0.
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.next_u64() % n
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn test_rng_below() {
+        let mut rng = Rng::new(1);
+        assert_eq!(0, rng.below(0));
+        for _ in 0..100 {
+            assert!(rng.below(5) < 5);
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! An in-memory network that stands in for real sockets during a simulation.
+//!
+//! Connections are a pair of in-process pipes. Every fault the network injects
+//! (delays, lost segments, short writes, resets and bursts of spurious
+//! `WouldBlock`s) is drawn from the simulation's seeded RNG, so a failing run
+//! can be replayed exactly.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use super::Rng;
+use crate::SIMULATION;
+use crate::executor::{self, Waiting};
+use crate::time::{self, Sleep};
+
+/// Controls the behaviour of the simulated network. Probabilities are in the
+/// range `0.0..=1.0` and are rolled independently for every operation.
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    /// One-way delay applied to every segment and handshake.
+    pub latency: Duration,
+    /// Extra random delay of up to this much added on top of `latency`.
+    pub jitter: Duration,
+    /// Probability that a segment is lost. Streams are reliable, so a lost
+    /// segment is retransmitted after `retransmit_timeout` and holds up
+    /// everything sent after it, as it would on a real TCP connection.
+    pub loss: f64,
+    pub retransmit_timeout: Duration,
+    /// Probability that a write only accepts part of the buffer.
+    pub partial_writes: f64,
+    /// Probability that a read or write resets the connection.
+    pub reset: f64,
+    /// Probability that a read or write starts a storm of spurious
+    /// `WouldBlock`s, lasting up to `storm_length` attempts.
+    pub would_block: f64,
+    pub storm_length: u32,
+    /// Bytes a connection can have in flight in each direction before writes
+    /// start to block.
+    pub buffer_size: usize,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            retransmit_timeout: Duration::from_millis(200),
+            partial_writes: 0.0,
+            reset: 0.0,
+            would_block: 0.0,
+            storm_length: 8,
+            buffer_size: 64 * 1024,
+        }
+    }
+}
+
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+pub(crate) struct Network {
+    config: NetConfig,
+    listeners: HashMap<SocketAddr, Rc<RefCell<ListenerState>>>,
+    next_port: u16,
+}
+
+impl Network {
+    pub(crate) fn new(config: NetConfig) -> Self {
+        Self {
+            config,
+            listeners: HashMap::new(),
+            next_port: EPHEMERAL_PORT_START,
+        }
+    }
+
+    fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = self
+            .next_port
+            .checked_add(1)
+            .unwrap_or(EPHEMERAL_PORT_START);
+        port
+    }
+
+    /// How long a segment sent now takes to reach the other side.
+    fn transit(&self, rng: &mut Rng) -> Duration {
+        let mut delay = self.config.latency;
+        if !self.config.jitter.is_zero() {
+            delay += self.config.jitter.mul_f64(rng.unit());
+        }
+        if rng.chance(self.config.loss) {
+            delay += self.config.retransmit_timeout;
+        }
+        delay
+    }
+}
+
+fn with_network<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut Network, &mut Rng) -> T,
+{
+    SIMULATION.with_borrow_mut(|sim| {
+        let sim = sim.as_mut().expect("Simulation not started on this thread");
+        f(&mut sim.network, &mut sim.rng)
+    })
+}
+
+struct ListenerState {
+    backlog: VecDeque<Stream>,
+    max_backlog: usize,
+    waker: Option<Waker>,
+}
+
+pub(crate) struct Listener {
+    addr: SocketAddr,
+    state: Rc<RefCell<ListenerState>>,
+}
+
+impl Listener {
+    pub(crate) fn bind(addr: SocketAddr, backlog_size: i32) -> io::Result<Self> {
+        with_network(|net, _| {
+            let mut addr = addr;
+            if addr.port() == 0 {
+                addr.set_port(net.ephemeral_port());
+            }
+            if net.listeners.contains_key(&addr) {
+                return Err(io::Error::from(io::ErrorKind::AddrInUse));
+            }
+
+            let state = Rc::new(RefCell::new(ListenerState {
+                backlog: VecDeque::new(),
+                max_backlog: backlog_size.max(1) as usize,
+                waker: None,
+            }));
+            net.listeners.insert(addr, state.clone());
+            Ok(Self { addr, state })
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub(crate) fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(Stream, SocketAddr)>> {
+        let mut state = self.state.borrow_mut();
+        match state.backlog.pop_front() {
+            Some(stream) => {
+                let peer = stream.peer;
+                Poll::Ready(Ok((stream, peer)))
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        // Connections still waiting in the backlog are dropped here, which
+        // closes them for the peers that were connecting.
+        SIMULATION.with_borrow_mut(|sim| {
+            if let Some(sim) = sim {
+                sim.network.listeners.remove(&self.addr);
+            }
+        });
+    }
+}
+
+/// Connects to a simulated listener, taking a round trip for the handshake.
+pub(crate) async fn connect(addr: SocketAddr) -> io::Result<Stream> {
+    let syn = with_network(|net, rng| net.transit(rng));
+    time::sleep(syn).await;
+
+    let (listener, local, syn_ack) = with_network(|net, rng| {
+        let ip = match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        };
+        let local = SocketAddr::new(ip, net.ephemeral_port());
+        (net.listeners.get(&addr).cloned(), local, net.transit(rng))
+    });
+
+    let client = listener.and_then(|listener| {
+        let mut listener = listener.borrow_mut();
+        if listener.backlog.len() >= listener.max_backlog {
+            return None;
+        }
+
+        let (client, server) = Stream::pair(local, addr);
+        listener.backlog.push_back(server);
+        if let Some(waker) = listener.waker.take() {
+            waker.wake();
+        }
+        Some(client)
+    });
+
+    time::sleep(syn_ack).await;
+    client.ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused))
+}
+
+struct Segment {
+    arrival: Instant,
+    data: Vec<u8>,
+}
+
+/// One direction of a connection.
+#[derive(Default)]
+struct Pipe {
+    segments: VecDeque<Segment>,
+    buffered: usize,
+    /// Set once the writing side shuts down, to the time the FIN arrives.
+    fin: Option<Instant>,
+    /// Set once the reading side is gone, so writes fail.
+    reader_closed: bool,
+    reset: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Pipe {
+    /// Segments can't overtake each other, so a delayed segment holds up
+    /// everything sent after it.
+    fn arrival(&self, now: Instant, transit: Duration) -> Instant {
+        let arrival = now + transit;
+        match self.segments.back() {
+            Some(last) => arrival.max(last.arrival),
+            None => arrival,
+        }
+    }
+
+    fn wake_all(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.writer.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) struct Stream {
+    local: SocketAddr,
+    peer: SocketAddr,
+    rx: Rc<RefCell<Pipe>>,
+    tx: Rc<RefCell<Pipe>>,
+    /// Remaining spurious `WouldBlock`s in the current storm.
+    storm: u32,
+    /// Wakes the reader when the next in-flight segment arrives.
+    delivery: Option<Sleep>,
+}
+
+impl Stream {
+    fn pair(client: SocketAddr, server: SocketAddr) -> (Self, Self) {
+        let up = Rc::new(RefCell::new(Pipe::default()));
+        let down = Rc::new(RefCell::new(Pipe::default()));
+        let client_end = Self::new(client, server, down.clone(), up.clone());
+        let server_end = Self::new(server, client, up, down);
+        (client_end, server_end)
+    }
+
+    fn new(
+        local: SocketAddr,
+        peer: SocketAddr,
+        rx: Rc<RefCell<Pipe>>,
+        tx: Rc<RefCell<Pipe>>,
+    ) -> Self {
+        Self {
+            local,
+            peer,
+            rx,
+            tx,
+            storm: 0,
+            delivery: None,
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    pub(crate) fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub(crate) fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(poll) = self.inject_faults(cx) {
+            return poll;
+        }
+
+        let now = time::now();
+        let mut rx = self.rx.borrow_mut();
+        if rx.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(segment) = rx.segments.front_mut() else {
+                break;
+            };
+            if segment.arrival > now {
+                break;
+            }
+
+            let n = segment.data.len().min(buf.len() - read);
+            buf[read..read + n].copy_from_slice(&segment.data[..n]);
+            segment.data.drain(..n);
+            if segment.data.is_empty() {
+                rx.segments.pop_front();
+            }
+            read += n;
+        }
+
+        if read > 0 {
+            rx.buffered -= read;
+            if let Some(waker) = rx.writer.take() {
+                waker.wake();
+            }
+            return Poll::Ready(Ok(read));
+        }
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let next = match (rx.segments.front(), rx.fin) {
+            (Some(segment), _) => Some(segment.arrival),
+            (None, Some(fin)) if fin <= now => return Poll::Ready(Ok(0)),
+            (None, fin) => fin,
+        };
+
+        rx.reader = Some(cx.waker().clone());
+        drop(rx);
+
+        // Data or a FIN is on its way, so make sure we are woken when it lands.
+        if let Some(next) = next {
+            if self
+                .delivery
+                .as_ref()
+                .is_none_or(|sleep| sleep.deadline() != next)
+            {
+                self.delivery = Some(time::sleep_until(next));
+            }
+            let sleep = self.delivery.as_mut().unwrap();
+            if Pin::new(sleep).poll(cx).is_ready() {
+                self.delivery = None;
+                cx.waker().wake_by_ref();
+            }
+        }
+
+        executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+        Poll::Pending
+    }
+
+    pub(crate) fn poll_write(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(poll) = self.inject_faults(cx) {
+            return poll;
+        }
+
+        let mut tx = self.tx.borrow_mut();
+        if tx.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        if tx.reader_closed || tx.fin.is_some() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let (capacity, partial, transit) = with_network(|net, rng| {
+            let partial = rng.chance(net.config.partial_writes);
+            (
+                net.config.buffer_size,
+                partial.then(|| rng.below(buf.len() as u64) as usize + 1),
+                net.transit(rng),
+            )
+        });
+
+        let space = capacity.saturating_sub(tx.buffered);
+        if space == 0 {
+            tx.writer = Some(cx.waker().clone());
+            executor::record_wait(Waiting::Resource(std::any::type_name::<Self>()));
+            return Poll::Pending;
+        }
+
+        let n = partial.unwrap_or(buf.len()).min(space);
+        let arrival = tx.arrival(time::now(), transit);
+        tx.segments.push_back(Segment {
+            arrival,
+            data: buf[..n].to_vec(),
+        });
+        tx.buffered += n;
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    pub(crate) fn shutdown(&mut self, how: Shutdown) {
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.shutdown_write();
+        }
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            let mut rx = self.rx.borrow_mut();
+            rx.reader_closed = true;
+            if let Some(waker) = rx.writer.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn shutdown_write(&mut self) {
+        let mut tx = self.tx.borrow_mut();
+        if tx.fin.is_some() {
+            return;
+        }
+
+        // The runtime may be shutting down, in which case there is no one left
+        // to observe the FIN's timing.
+        let transit = SIMULATION.with_borrow_mut(|sim| match sim {
+            Some(sim) => sim.network.transit(&mut sim.rng),
+            None => Duration::ZERO,
+        });
+        tx.fin = Some(tx.arrival(time::now(), transit));
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+    }
+
+    /// Rolls for resets and `WouldBlock` storms before an operation. Returns the
+    /// result the operation should report instead, if any.
+    fn inject_faults<T>(&mut self, cx: &mut Context<'_>) -> Option<Poll<io::Result<T>>> {
+        if self.storm == 0 {
+            let (storm, reset) = with_network(|net, rng| {
+                let storm = if rng.chance(net.config.would_block) {
+                    rng.below(net.config.storm_length as u64) as u32 + 1
+                } else {
+                    0
+                };
+                (storm, rng.chance(net.config.reset))
+            });
+
+            if reset {
+                self.reset();
+                return Some(Poll::Ready(Err(io::ErrorKind::ConnectionReset.into())));
+            }
+            self.storm = storm;
+        }
+
+        if self.storm > 0 {
+            // The equivalent of EAGAIN on a socket that is actually ready: the
+            // caller gets woken straight away and has to retry.
+            self.storm -= 1;
+            cx.waker().wake_by_ref();
+            return Some(Poll::Pending);
+        }
+
+        None
+    }
+
+    fn reset(&mut self) {
+        for pipe in [&self.rx, &self.tx] {
+            let mut pipe = pipe.borrow_mut();
+            pipe.reset = true;
+            pipe.segments.clear();
+            pipe.buffered = 0;
+            pipe.wake_all();
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.shutdown(Shutdown::Both);
+    }
+}
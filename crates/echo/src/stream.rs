@@ -0,0 +1,392 @@
+//! Asynchronous sequences of values, and combinators for working with them.
+//!
+//! The combinators in [`StreamExt`] need the stream to be `Unpin`. Streams
+//! that aren't can be pinned with `Box::pin` first.
+
+use std::ops::DerefMut;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use crate::time::{self, Sleep};
+
+/// The asynchronous version of `Iterator`.
+pub trait Stream {
+    type Item;
+
+    /// Returns the next value, `None` once the stream has ended, or `Pending`
+    /// after registering the task to be woken when a value might be ready.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+
+    /// Bounds on how many values are left, as with `Iterator::size_hint`.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
+}
+
+impl<S: Stream + Unpin + ?Sized> Stream for &mut S {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+}
+
+impl<S: Stream + Unpin + ?Sized> Stream for Box<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+}
+
+impl<P> Stream for Pin<P>
+where
+    P: DerefMut<Target: Stream>,
+{
+    type Item = <P::Target as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.as_deref_mut().poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+}
+
+/// Combinators for every [`Stream`].
+pub trait StreamExt: Stream {
+    /// Waits for the next value, or `None` once the stream has ended.
+    fn next(&mut self) -> Next<'_, Self>
+    where
+        Self: Unpin,
+    {
+        Next { stream: self }
+    }
+
+    fn map<T, F>(self, f: F) -> Map<Self, F>
+    where
+        F: FnMut(Self::Item) -> T,
+        Self: Sized,
+    {
+        Map { stream: self, f }
+    }
+
+    /// Skips the values `f` returns false for.
+    fn filter<F>(self, f: F) -> Filter<Self, F>
+    where
+        F: FnMut(&Self::Item) -> bool,
+        Self: Sized,
+    {
+        Filter { stream: self, f }
+    }
+
+    /// Ends the stream after `n` values.
+    fn take(self, n: usize) -> Take<Self>
+    where
+        Self: Sized,
+    {
+        Take {
+            stream: self,
+            remaining: n,
+        }
+    }
+
+    /// Runs the futures from a stream of futures, up to `limit` at a time, and
+    /// yields their outputs in the order they finish.
+    fn buffer_unordered(self, limit: usize) -> BufferUnordered<Self>
+    where
+        Self::Item: Future,
+        Self: Sized,
+    {
+        assert!(limit > 0, "buffer_unordered needs a limit of at least one");
+        BufferUnordered {
+            stream: self,
+            in_flight: Vec::new(),
+            limit,
+            done: false,
+        }
+    }
+
+    /// Lets at most one value through per `period`, holding later ones back
+    /// until the period since the last one has passed.
+    fn throttle(self, period: Duration) -> Throttle<Self>
+    where
+        Self: Sized,
+    {
+        Throttle {
+            stream: self,
+            period,
+            delay: None,
+        }
+    }
+}
+
+impl<S: Stream + ?Sized> StreamExt for S {}
+
+pub struct Next<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+pub struct Map<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F, T> Stream for Map<S, F>
+where
+    S: Stream + Unpin,
+    F: FnMut(S::Item) -> T + Unpin,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.stream)
+            .poll_next(cx)
+            .map(|item| item.map(&mut this.f))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+pub struct Filter<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F> Stream for Filter<S, F>
+where
+    S: Stream + Unpin,
+    F: FnMut(&S::Item) -> bool + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        loop {
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(item) if !(this.f)(&item) => {}
+                item => return Poll::Ready(item),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.stream.size_hint().1)
+    }
+}
+
+pub struct Take<S> {
+    stream: S,
+    remaining: usize,
+}
+
+impl<S: Stream + Unpin> Stream for Take<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        let item = ready!(Pin::new(&mut this.stream).poll_next(cx));
+        match item {
+            Some(_) => this.remaining -= 1,
+            None => this.remaining = 0,
+        }
+        Poll::Ready(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.stream.size_hint();
+        let upper = upper.map_or(self.remaining, |upper| upper.min(self.remaining));
+        (lower.min(self.remaining), Some(upper))
+    }
+}
+
+pub struct BufferUnordered<S: Stream<Item: Future>> {
+    stream: S,
+    in_flight: Vec<Pin<Box<S::Item>>>,
+    limit: usize,
+    /// Whether `stream` has ended.
+    done: bool,
+}
+
+impl<S> Stream for BufferUnordered<S>
+where
+    S: Stream<Item: Future> + Unpin,
+{
+    type Item = <S::Item as Future>::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.done && this.in_flight.len() < this.limit {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(fut)) => this.in_flight.push(Box::pin(fut)),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        // Every future shares the task's waker, so there's no telling which
+        // one woke it. Poll them all.
+        for i in 0..this.in_flight.len() {
+            if let Poll::Ready(output) = this.in_flight[i].as_mut().poll(cx) {
+                drop(this.in_flight.swap_remove(i));
+                return Poll::Ready(Some(output));
+            }
+        }
+
+        if this.done && this.in_flight.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.stream.size_hint();
+        let n = self.in_flight.len();
+        (
+            lower.saturating_add(n),
+            upper.and_then(|u| u.checked_add(n)),
+        )
+    }
+}
+
+pub struct Throttle<S> {
+    stream: S,
+    period: Duration,
+    /// Runs from the last value that was let through.
+    delay: Option<Sleep>,
+}
+
+impl<S: Stream + Unpin> Stream for Throttle<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        if let Some(delay) = &mut this.delay {
+            ready!(Pin::new(delay).poll(cx));
+            this.delay = None;
+        }
+
+        let item = ready!(Pin::new(&mut this.stream).poll_next(cx));
+        if item.is_some() {
+            this.delay = Some(time::sleep(this.period));
+        }
+        Poll::Ready(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+/// Yields the values of an iterator, without ever waiting.
+pub fn iter<I: IntoIterator>(iter: I) -> Iter<I::IntoIter> {
+    Iter {
+        iter: iter.into_iter(),
+    }
+}
+
+pub struct Iter<I> {
+    iter: I,
+}
+
+impl<I: Iterator + Unpin> Stream for Iter<I> {
+    type Item = I::Item;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.get_mut().iter.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::runtime::Builder;
+    use crate::time;
+
+    #[tokio::test]
+    async fn test_map_filter_take() {
+        let mut s = iter(1..).map(|i| i * 3).filter(|i| i % 2 == 0).take(3);
+        assert_eq!((0, Some(3)), s.size_hint());
+        let mut all = Vec::new();
+        while let Some(i) = s.next().await {
+            all.push(i);
+        }
+        assert_eq!(vec![6, 12, 18], all);
+    }
+
+    #[test]
+    fn test_buffer_unordered() {
+        let (order, elapsed) = Builder::simulation(0)
+            .run(async {
+                let start = time::now();
+                let delays = [30, 10, 20, 10];
+                let mut s = iter(delays.into_iter().enumerate())
+                    .map(|(i, ms)| async move {
+                        time::sleep(Duration::from_millis(ms)).await;
+                        i
+                    })
+                    .buffer_unordered(2);
+
+                let mut order = Vec::new();
+                while let Some(i) = s.next().await {
+                    order.push(i);
+                }
+                (order, time::now() - start)
+            })
+            .unwrap();
+
+        // 0 and 1 start together, and 2 takes 1's place at 10ms. 0 and 2 both
+        // finish at 30ms, and 3 takes 0's place.
+        assert_eq!(vec![1, 0, 2, 3], order);
+        assert_eq!(Duration::from_millis(40), elapsed);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let times = Builder::simulation(0)
+            .run(async {
+                let start = time::now();
+                let mut s = iter(0..3).throttle(Duration::from_millis(100));
+                let mut times = Vec::new();
+                while s.next().await.is_some() {
+                    times.push(time::now() - start);
+                }
+                times
+            })
+            .unwrap();
+        let ms = |ms| Duration::from_millis(ms);
+        assert_eq!(vec![ms(0), ms(100), ms(200)], times);
+    }
+}
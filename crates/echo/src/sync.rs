@@ -0,0 +1,42 @@
+//! Synchronization primitives for sharing state and signalling between tasks.
+//!
+//! Unlike `RefCell` or `std::sync::Mutex`, the locks here can be held across an
+//! `.await`: a task that can't get the lock yields to the executor until it is
+//! released. Waiters are served in FIFO order, so a steady stream of new lockers
+//! can't starve a task that is already waiting.
+//!
+//! The types at the top level are for the single threaded runtime and can't be
+//! sent across threads. [`mt`] has `Send` + `Sync` versions with the same API.
+
+mod barrier;
+mod flavor;
+pub mod mt;
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+
+pub use self::barrier::BarrierWaitResult;
+pub use self::mutex::TryLockError;
+pub use self::semaphore::TryAcquireError;
+
+use self::flavor::Local;
+
+pub type Semaphore = semaphore::Semaphore<Local>;
+pub type SemaphorePermit<'a> = semaphore::SemaphorePermit<'a, Local>;
+pub type OwnedSemaphorePermit = semaphore::OwnedSemaphorePermit<Local>;
+
+pub type Mutex<T> = mutex::Mutex<T, Local>;
+pub type MutexGuard<'a, T> = mutex::MutexGuard<'a, T, Local>;
+pub type OwnedMutexGuard<T> = mutex::OwnedMutexGuard<T, Local>;
+
+pub type RwLock<T> = rwlock::RwLock<T, Local>;
+pub type RwLockReadGuard<'a, T> = rwlock::RwLockReadGuard<'a, T, Local>;
+pub type RwLockWriteGuard<'a, T> = rwlock::RwLockWriteGuard<'a, T, Local>;
+pub type OwnedRwLockReadGuard<T> = rwlock::OwnedRwLockReadGuard<T, Local>;
+pub type OwnedRwLockWriteGuard<T> = rwlock::OwnedRwLockWriteGuard<T, Local>;
+
+pub type Notify = notify::Notify<Local>;
+pub type Notified<'a> = notify::Notified<'a, Local>;
+
+pub type Barrier = barrier::Barrier<Local>;
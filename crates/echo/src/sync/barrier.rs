@@ -0,0 +1,160 @@
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use super::flavor::{self, Flavor, StateCell};
+
+pub struct State {
+    arrived: usize,
+    waiters: Vec<Waker>,
+    /// Bumped each time the barrier releases, so it can be reused.
+    generation: u64,
+}
+
+/// Holds tasks back until `n` of them are waiting, then releases them all.
+pub struct Barrier<F: Flavor> {
+    n: usize,
+    state: F::Cell<State>,
+}
+
+impl<F: Flavor> Barrier<F> {
+    /// A barrier for `n` tasks. A barrier for zero tasks behaves like one for a
+    /// single task and never waits.
+    pub fn new(n: usize) -> Self {
+        Self {
+            n: n.max(1),
+            state: F::Cell::new(State {
+                arrived: 0,
+                waiters: Vec::new(),
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Waits for the rest of the tasks to arrive. The last task to arrive is
+    /// told it is the leader.
+    ///
+    /// A task counts as arrived from the first poll, even if the future is
+    /// dropped afterwards.
+    pub fn wait(&self) -> Wait<'_, F> {
+        Wait {
+            barrier: self,
+            arrival: None,
+        }
+    }
+}
+
+pub struct Wait<'a, F: Flavor> {
+    barrier: &'a Barrier<F>,
+    /// The generation this task arrived in, and its slot in `waiters`.
+    arrival: Option<(u64, usize)>,
+}
+
+impl<F: Flavor> Future for Wait<'_, F> {
+    type Output = BarrierWaitResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let n = this.barrier.n;
+        let (poll, release) = this.barrier.state.with(|s| match this.arrival {
+            Some((generation, _)) if generation != s.generation => {
+                (Poll::Ready(BarrierWaitResult(false)), Vec::new())
+            }
+            Some((_, slot)) => {
+                s.waiters[slot] = cx.waker().clone();
+                (Poll::Pending, Vec::new())
+            }
+            None if s.arrived + 1 == n => {
+                s.arrived = 0;
+                s.generation += 1;
+                let release = std::mem::take(&mut s.waiters);
+                (Poll::Ready(BarrierWaitResult(true)), release)
+            }
+            None => {
+                s.arrived += 1;
+                this.arrival = Some((s.generation, s.waiters.len()));
+                s.waiters.push(cx.waker().clone());
+                (Poll::Pending, Vec::new())
+            }
+        });
+
+        for waker in release {
+            waker.wake();
+        }
+
+        if poll.is_pending() {
+            flavor::record_wait::<F>(std::any::type_name::<Self>());
+        }
+        poll
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Exactly one task per release of the barrier is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::runtime::Builder;
+    use crate::sync::Barrier;
+    use crate::{executor, time};
+
+    #[test]
+    fn test_barrier_releases_together() {
+        for seed in 0..10 {
+            let log = Builder::simulation(seed)
+                .run(async {
+                    let barrier = Rc::new(Barrier::new(4));
+                    let log = Rc::new(RefCell::new(Vec::new()));
+                    let handles: Vec<_> = (0..4u64)
+                        .map(|i| {
+                            let (barrier, log) = (barrier.clone(), log.clone());
+                            executor::spawn(async move {
+                                time::sleep(Duration::from_millis(i * 10)).await;
+                                log.borrow_mut().push("arrive");
+                                let leader = barrier.wait().await.is_leader();
+                                log.borrow_mut().push("leave");
+                                leader
+                            })
+                        })
+                        .collect();
+
+                    let mut leaders = 0;
+                    for handle in handles {
+                        leaders += handle.await.unwrap() as usize;
+                    }
+                    assert_eq!(1, leaders);
+                    log.take()
+                })
+                .unwrap();
+            assert_eq!(vec!["arrive"; 4], log[..4]);
+            assert_eq!(vec!["leave"; 4], log[4..]);
+        }
+    }
+
+    #[test]
+    fn test_barrier_is_reusable() {
+        Builder::simulation(0)
+            .run(async {
+                let barrier = Rc::new(Barrier::new(2));
+                let other = barrier.clone();
+                let handle = executor::spawn(async move {
+                    other.wait().await;
+                    other.wait().await;
+                });
+                barrier.wait().await;
+                barrier.wait().await;
+                handle.await.unwrap();
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::executor::{self, Waiting};
+
+/// Selects how a primitive's state is shared: through a `RefCell` and `Rc` on
+/// the single threaded runtime, or a `std::sync::Mutex` and `Arc` when it has
+/// to cross threads.
+pub trait Flavor: 'static {
+    type Cell<S>: StateCell<S>;
+    type Ptr<T: ?Sized>: Deref<Target = T> + Clone;
+    /// Whether waiters may be woken from other threads.
+    const SHARED: bool;
+}
+
+/// Records that the current task is waiting on the primitive `name`, for
+/// deadlock reports.
+pub(super) fn record_wait<F: Flavor>(name: &'static str) {
+    executor::record_wait(if F::SHARED {
+        Waiting::Remote(name)
+    } else {
+        Waiting::Resource(name)
+    });
+}
+
+pub trait StateCell<S> {
+    fn new(state: S) -> Self;
+    fn with<R>(&self, f: impl FnOnce(&mut S) -> R) -> R;
+}
+
+pub struct Local;
+
+impl Flavor for Local {
+    type Cell<S> = RefCell<S>;
+    type Ptr<T: ?Sized> = Rc<T>;
+    const SHARED: bool = false;
+}
+
+impl<S> StateCell<S> for RefCell<S> {
+    fn new(state: S) -> Self {
+        RefCell::new(state)
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut S) -> R) -> R {
+        f(&mut self.borrow_mut())
+    }
+}
+
+pub struct Shared;
+
+impl Flavor for Shared {
+    type Cell<S> = std::sync::Mutex<S>;
+    type Ptr<T: ?Sized> = Arc<T>;
+    const SHARED: bool = true;
+}
+
+impl<S> StateCell<S> for std::sync::Mutex<S> {
+    fn new(state: S) -> Self {
+        std::sync::Mutex::new(state)
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut S) -> R) -> R {
+        // The state is only touched by our own short critical sections, none of
+        // which can panic while leaving it inconsistent.
+        let mut state = self.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut state)
+    }
+}
@@ -0,0 +1,126 @@
+//! `Send` + `Sync` versions of the primitives in [`crate::sync`], for sharing
+//! state with tasks on other threads. They work the same way, but keep their
+//! waiter queue behind a `std::sync::Mutex` and hand out `Arc`s from the owned
+//! methods.
+
+use super::flavor::Shared;
+use super::{barrier, mutex, notify, rwlock, semaphore};
+
+pub type Semaphore = semaphore::Semaphore<Shared>;
+pub type SemaphorePermit<'a> = semaphore::SemaphorePermit<'a, Shared>;
+pub type OwnedSemaphorePermit = semaphore::OwnedSemaphorePermit<Shared>;
+
+pub type Mutex<T> = mutex::Mutex<T, Shared>;
+pub type MutexGuard<'a, T> = mutex::MutexGuard<'a, T, Shared>;
+pub type OwnedMutexGuard<T> = mutex::OwnedMutexGuard<T, Shared>;
+
+pub type RwLock<T> = rwlock::RwLock<T, Shared>;
+pub type RwLockReadGuard<'a, T> = rwlock::RwLockReadGuard<'a, T, Shared>;
+pub type RwLockWriteGuard<'a, T> = rwlock::RwLockWriteGuard<'a, T, Shared>;
+pub type OwnedRwLockReadGuard<T> = rwlock::OwnedRwLockReadGuard<T, Shared>;
+pub type OwnedRwLockWriteGuard<T> = rwlock::OwnedRwLockWriteGuard<T, Shared>;
+
+pub type Notify = notify::Notify<Shared>;
+pub type Notified<'a> = notify::Notified<'a, Shared>;
+
+pub type Barrier = barrier::Barrier<Shared>;
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<Semaphore>();
+        assert_send_sync::<Mutex<Vec<u8>>>();
+        assert_send_sync::<RwLock<Vec<u8>>>();
+        assert_send_sync::<OwnedMutexGuard<Vec<u8>>>();
+        assert_send_sync::<Notify>();
+        assert_send_sync::<Barrier>();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_notify_and_barrier_across_threads() {
+        let barrier = Arc::new(Barrier::new(5));
+        let notify = Arc::new(Notify::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (barrier, notify) = (barrier.clone(), notify.clone());
+                tokio::spawn(async move {
+                    let notified = notify.notified();
+                    barrier.wait().await;
+                    notified.await;
+                })
+            })
+            .collect();
+
+        // Everyone has created their `Notified` once the barrier releases.
+        barrier.wait().await;
+        notify.notify_waiters();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mutex_across_threads() {
+        let counter = Arc::new(Mutex::new(0));
+        let handles: Vec<_> = (0..50)
+            .map(|_| {
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut guard = counter.lock_owned().await;
+                    let seen = *guard;
+                    tokio::task::yield_now().await;
+                    *guard = seen + 1;
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(50, *counter.lock().await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_semaphore_limits_concurrency() {
+        let semaphore = Arc::new(Semaphore::new(3));
+        let running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let (semaphore, running) = (semaphore.clone(), running.clone());
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire().await;
+                    let now = running.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    assert!(now < 3);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    running.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(3, semaphore.available_permits());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rwlock_across_threads() {
+        let lock = Arc::new(RwLock::new(Vec::new()));
+        let writers: Vec<_> = (0..10)
+            .map(|i| {
+                let lock = lock.clone();
+                tokio::spawn(async move { lock.write_owned().await.push(i) })
+            })
+            .collect();
+        for handle in writers {
+            handle.await.unwrap();
+        }
+        assert_eq!(10, lock.read().await.len());
+    }
+}
@@ -0,0 +1,244 @@
+use std::cell::UnsafeCell;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::flavor::{Flavor, Local, Shared};
+use super::semaphore::{Semaphore, State};
+
+/// A mutex that can be held across an `.await`. Built on a semaphore with a
+/// single permit.
+pub struct Mutex<T: ?Sized, F: Flavor> {
+    semaphore: Semaphore<F>,
+    data: UnsafeCell<T>,
+}
+
+// Only one guard can exist at a time, so the mutex hands out exclusive access
+// to `T` and needs it to be `Send`, just like `std::sync::Mutex`. Whether it
+// can be shared at all is decided by the flavor.
+unsafe impl<T: ?Sized + Send, F: Flavor> Sync for Mutex<T, F> where F::Cell<State>: Sync {}
+
+impl<T, F: Flavor> Mutex<T, F> {
+    pub fn new(value: T) -> Self {
+        Self {
+            semaphore: Semaphore::new(1),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Mutex<T, F> {
+    pub async fn lock(&self) -> MutexGuard<'_, T, F> {
+        self.semaphore.acquire().await.forget();
+        MutexGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T, F>, TryLockError> {
+        self.semaphore
+            .try_acquire()
+            .map_err(|_| TryLockError)?
+            .forget();
+        Ok(MutexGuard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+
+    /// No locking is needed when the mutex is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    async fn lock_owned_inner(this: F::Ptr<Self>) -> OwnedMutexGuard<T, F> {
+        this.semaphore.acquire().await.forget();
+        OwnedMutexGuard {
+            lock: this,
+            _marker: PhantomData,
+        }
+    }
+
+    fn try_lock_owned_inner(this: F::Ptr<Self>) -> Result<OwnedMutexGuard<T, F>, TryLockError> {
+        this.semaphore
+            .try_acquire()
+            .map_err(|_| TryLockError)?
+            .forget();
+        Ok(OwnedMutexGuard {
+            lock: this,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T: ?Sized> Mutex<T, Local> {
+    /// Like [`Mutex::lock`], but the guard keeps the mutex alive instead of
+    /// borrowing it, so it can be moved into another task.
+    pub async fn lock_owned(self: Rc<Self>) -> OwnedMutexGuard<T, Local> {
+        Self::lock_owned_inner(self).await
+    }
+
+    pub fn try_lock_owned(self: Rc<Self>) -> Result<OwnedMutexGuard<T, Local>, TryLockError> {
+        Self::try_lock_owned_inner(self)
+    }
+}
+
+impl<T: ?Sized> Mutex<T, Shared> {
+    /// Like [`Mutex::lock`], but the guard keeps the mutex alive instead of
+    /// borrowing it, so it can be moved into another task.
+    pub async fn lock_owned(self: Arc<Self>) -> OwnedMutexGuard<T, Shared> {
+        Self::lock_owned_inner(self).await
+    }
+
+    pub fn try_lock_owned(self: Arc<Self>) -> Result<OwnedMutexGuard<T, Shared>, TryLockError> {
+        Self::try_lock_owned_inner(self)
+    }
+}
+
+impl<T: Default, F: Flavor> Default for Mutex<T, F> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized, F: Flavor> {
+    lock: &'a Mutex<T, F>,
+    // Makes the guard only as `Send`/`Sync` as a `&mut T` would be.
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T: ?Sized, F: Flavor> Deref for MutexGuard<'_, T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the mutex's only permit.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> DerefMut for MutexGuard<'_, T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the mutex's only permit.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Drop for MutexGuard<'_, T, F> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+pub struct OwnedMutexGuard<T: ?Sized, F: Flavor> {
+    lock: F::Ptr<Mutex<T, F>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ?Sized, F: Flavor> OwnedMutexGuard<T, F> {
+    pub fn mutex(&self) -> &F::Ptr<Mutex<T, F>> {
+        &self.lock
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Deref for OwnedMutexGuard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the mutex's only permit.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> DerefMut for OwnedMutexGuard<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the mutex's only permit.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Drop for OwnedMutexGuard<T, F> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TryLockError;
+
+impl Display for TryLockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lock is held elsewhere")
+    }
+}
+
+impl std::error::Error for TryLockError {}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::runtime::Builder;
+    use crate::sync::{Mutex, TryLockError};
+    use crate::{executor, time};
+
+    #[test]
+    fn test_lock_across_await() {
+        let total = Builder::simulation(0)
+            .run(async {
+                let counter = Rc::new(Mutex::new(0));
+                let handles: Vec<_> = (0..10)
+                    .map(|_| {
+                        let counter = counter.clone();
+                        executor::spawn(async move {
+                            let mut guard = counter.lock().await;
+                            let seen = *guard;
+                            time::sleep(Duration::from_millis(1)).await;
+                            *guard = seen + 1;
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+                Rc::try_unwrap(counter).ok().unwrap().into_inner()
+            })
+            .unwrap();
+        assert_eq!(10, total);
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let mutex = Mutex::new(1);
+        let guard = mutex.try_lock().unwrap();
+        assert_eq!(TryLockError, mutex.try_lock().err().unwrap());
+        drop(guard);
+        *mutex.try_lock().unwrap() += 1;
+        assert_eq!(2, mutex.into_inner());
+    }
+
+    #[test]
+    fn test_owned_guard() {
+        Builder::simulation(0)
+            .run(async {
+                let mutex = Rc::new(Mutex::new(Vec::new()));
+                let mut guard = mutex.clone().lock_owned().await;
+                let other = mutex.clone();
+                let handle = executor::spawn(async move { other.lock().await.push(2) });
+                guard.push(1);
+                time::sleep(Duration::from_millis(1)).await;
+                assert!(mutex.try_lock().is_err());
+                drop(guard);
+                handle.await.unwrap();
+                assert_eq!(vec![1, 2], *mutex.lock().await);
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use super::flavor::{self, Flavor, StateCell};
+
+pub struct State {
+    /// Set by a `notify_one` that found nobody waiting, and used up by the
+    /// next waiter. There is never more than one.
+    permit: bool,
+    waiters: VecDeque<(u64, Waker)>,
+    /// Waiters that have been notified but not yet polled, and whether it was
+    /// by `notify_one`.
+    notified: HashMap<u64, bool>,
+    /// How many times `notify_waiters` has been called.
+    generation: u64,
+    next_id: u64,
+}
+
+impl State {
+    /// Returns the waker of the waiter that was notified, if there was one.
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self.waiters.pop_front() {
+            Some((id, waker)) => {
+                self.notified.insert(id, true);
+                Some(waker)
+            }
+            None => {
+                self.permit = true;
+                None
+            }
+        }
+    }
+}
+
+/// Wakes tasks waiting on it, without carrying any data.
+///
+/// [`Notify::notify_one`] wakes the oldest waiter, or if nobody is waiting,
+/// leaves a permit for the next one so that a notification sent just before a
+/// task starts waiting isn't lost. [`Notify::notify_waiters`] wakes everyone
+/// that is waiting at the time, and leaves nothing behind.
+pub struct Notify<F: Flavor> {
+    state: F::Cell<State>,
+}
+
+impl<F: Flavor> Notify<F> {
+    pub fn new() -> Self {
+        Self {
+            state: F::Cell::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+                notified: HashMap::new(),
+                generation: 0,
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Waits for a notification. A `notify_waiters` call made after this
+    /// returns, even before the future is first polled, counts.
+    pub fn notified(&self) -> Notified<'_, F> {
+        Notified {
+            notify: self,
+            generation: self.state.with(|s| s.generation),
+            waiter: None,
+        }
+    }
+
+    pub fn notify_one(&self) {
+        if let Some(waker) = self.state.with(State::notify_one) {
+            waker.wake();
+        }
+    }
+
+    pub fn notify_waiters(&self) {
+        let waiters = self.state.with(|s| {
+            s.generation += 1;
+            let waiters: Vec<_> = s.waiters.drain(..).collect();
+            for (id, _) in &waiters {
+                s.notified.insert(*id, false);
+            }
+            waiters
+        });
+
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+}
+
+impl<F: Flavor> Default for Notify<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Notified<'a, F: Flavor> {
+    notify: &'a Notify<F>,
+    generation: u64,
+    waiter: Option<u64>,
+}
+
+impl<F: Flavor> Future for Notified<'_, F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let ready = this.notify.state.with(|s| {
+            let Some(id) = this.waiter else {
+                if s.generation != this.generation || std::mem::take(&mut s.permit) {
+                    return true;
+                }
+
+                let id = s.next_id;
+                s.next_id += 1;
+                s.waiters.push_back((id, cx.waker().clone()));
+                this.waiter = Some(id);
+                return false;
+            };
+
+            if s.notified.remove(&id).is_some() {
+                this.waiter = None;
+                return true;
+            }
+
+            if let Some((_, waker)) = s.waiters.iter_mut().find(|(i, _)| *i == id)
+                && !waker.will_wake(cx.waker())
+            {
+                *waker = cx.waker().clone();
+            }
+            false
+        });
+
+        if ready {
+            return Poll::Ready(());
+        }
+
+        flavor::record_wait::<F>(std::any::type_name::<Self>());
+        Poll::Pending
+    }
+}
+
+impl<F: Flavor> Drop for Notified<'_, F> {
+    fn drop(&mut self) {
+        let Some(id) = self.waiter else {
+            return;
+        };
+
+        // A `notify_one` aimed at this waiter would be lost, so pass it on.
+        let waker = self.notify.state.with(|s| match s.notified.remove(&id) {
+            Some(true) => s.notify_one(),
+            Some(false) => None,
+            None => {
+                s.waiters.retain(|(i, _)| *i != id);
+                None
+            }
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::runtime::Builder;
+    use crate::sync::Notify;
+    use crate::{executor, time};
+
+    #[test]
+    fn test_notify_before_wait() {
+        Builder::simulation(0)
+            .run(async {
+                let notify = Notify::new();
+                notify.notify_one();
+                notify.notify_one();
+                // Only one permit is stored.
+                notify.notified().await;
+                let mut second = Box::pin(notify.notified());
+                let waker = std::task::Waker::noop();
+                let mut cx = std::task::Context::from_waker(waker);
+                assert!(second.as_mut().poll(&mut cx).is_pending());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_notify_one_wakes_in_order() {
+        let woken = Builder::simulation(1)
+            .run(async {
+                let notify = Rc::new(Notify::new());
+                let woken = Rc::new(Cell::new(0));
+                let handles: Vec<_> = (0..3)
+                    .map(|_| {
+                        let (notify, woken) = (notify.clone(), woken.clone());
+                        executor::spawn(async move {
+                            notify.notified().await;
+                            woken.set(woken.get() + 1);
+                        })
+                    })
+                    .collect();
+                time::sleep(Duration::from_millis(1)).await;
+
+                notify.notify_one();
+                time::sleep(Duration::from_millis(1)).await;
+                assert_eq!(1, woken.get());
+
+                notify.notify_waiters();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+                woken.get()
+            })
+            .unwrap();
+        assert_eq!(3, woken);
+    }
+
+    #[test]
+    fn test_notify_waiters_counts_unpolled_futures() {
+        Builder::simulation(0)
+            .run(async {
+                let notify = Notify::new();
+                let notified = notify.notified();
+                notify.notify_waiters();
+                notified.await;
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_dropped_waiter_passes_notification_on() {
+        Builder::simulation(0)
+            .run(async {
+                let notify = Rc::new(Notify::new());
+                let mut first = Box::pin(notify.notified());
+                let waker = std::task::Waker::noop();
+                let mut cx = std::task::Context::from_waker(waker);
+                assert!(first.as_mut().poll(&mut cx).is_pending());
+
+                let other = notify.clone();
+                let second = executor::spawn(async move { other.notified().await });
+                time::sleep(Duration::from_millis(1)).await;
+
+                notify.notify_one();
+                drop(first);
+                second.await.unwrap();
+            })
+            .unwrap();
+    }
+}
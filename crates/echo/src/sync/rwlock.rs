@@ -0,0 +1,277 @@
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::flavor::{Flavor, Local, Shared};
+use super::mutex::TryLockError;
+use super::semaphore::{Semaphore, State};
+
+/// Readers take one permit each and writers take all of them. Because the
+/// semaphore is FIFO, a waiting writer holds up readers that arrive after it.
+const MAX_READS: usize = usize::MAX >> 3;
+
+/// A reader-writer lock that can be held across an `.await`.
+pub struct RwLock<T: ?Sized, F: Flavor> {
+    semaphore: Semaphore<F>,
+    data: UnsafeCell<T>,
+}
+
+// Readers on different threads may see `T` at the same time, so unlike `Mutex`
+// this also needs `T: Sync`.
+unsafe impl<T: ?Sized + Send + Sync, F: Flavor> Sync for RwLock<T, F> where F::Cell<State>: Sync {}
+
+impl<T, F: Flavor> RwLock<T, F> {
+    pub fn new(value: T) -> Self {
+        Self {
+            semaphore: Semaphore::new(MAX_READS),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized, F: Flavor> RwLock<T, F> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, T, F> {
+        self.semaphore.acquire().await.forget();
+        RwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T, F> {
+        self.semaphore.acquire_many(MAX_READS).await.forget();
+        RwLockWriteGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn try_read(&self) -> Result<RwLockReadGuard<'_, T, F>, TryLockError> {
+        self.semaphore
+            .try_acquire()
+            .map_err(|_| TryLockError)?
+            .forget();
+        Ok(RwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn try_write(&self) -> Result<RwLockWriteGuard<'_, T, F>, TryLockError> {
+        self.semaphore
+            .try_acquire_many(MAX_READS)
+            .map_err(|_| TryLockError)?
+            .forget();
+        Ok(RwLockWriteGuard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    async fn read_owned_inner(this: F::Ptr<Self>) -> OwnedRwLockReadGuard<T, F> {
+        this.semaphore.acquire().await.forget();
+        OwnedRwLockReadGuard {
+            lock: this,
+            _marker: PhantomData,
+        }
+    }
+
+    async fn write_owned_inner(this: F::Ptr<Self>) -> OwnedRwLockWriteGuard<T, F> {
+        this.semaphore.acquire_many(MAX_READS).await.forget();
+        OwnedRwLockWriteGuard {
+            lock: this,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T, Local> {
+    pub async fn read_owned(self: Rc<Self>) -> OwnedRwLockReadGuard<T, Local> {
+        Self::read_owned_inner(self).await
+    }
+
+    pub async fn write_owned(self: Rc<Self>) -> OwnedRwLockWriteGuard<T, Local> {
+        Self::write_owned_inner(self).await
+    }
+}
+
+impl<T: ?Sized> RwLock<T, Shared> {
+    pub async fn read_owned(self: Arc<Self>) -> OwnedRwLockReadGuard<T, Shared> {
+        Self::read_owned_inner(self).await
+    }
+
+    pub async fn write_owned(self: Arc<Self>) -> OwnedRwLockWriteGuard<T, Shared> {
+        Self::write_owned_inner(self).await
+    }
+}
+
+impl<T: Default, F: Flavor> Default for RwLock<T, F> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized, F: Flavor> {
+    lock: &'a RwLock<T, F>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<T: ?Sized, F: Flavor> Deref for RwLockReadGuard<'_, T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: no writer can hold the lock while a read permit is out.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Drop for RwLockReadGuard<'_, T, F> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized, F: Flavor> {
+    lock: &'a RwLock<T, F>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T: ?Sized, F: Flavor> Deref for RwLockWriteGuard<'_, T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds every permit.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> DerefMut for RwLockWriteGuard<'_, T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds every permit.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Drop for RwLockWriteGuard<'_, T, F> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(MAX_READS);
+    }
+}
+
+pub struct OwnedRwLockReadGuard<T: ?Sized, F: Flavor> {
+    lock: F::Ptr<RwLock<T, F>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ?Sized, F: Flavor> Deref for OwnedRwLockReadGuard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: no writer can hold the lock while a read permit is out.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Drop for OwnedRwLockReadGuard<T, F> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+pub struct OwnedRwLockWriteGuard<T: ?Sized, F: Flavor> {
+    lock: F::Ptr<RwLock<T, F>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ?Sized, F: Flavor> Deref for OwnedRwLockWriteGuard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds every permit.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> DerefMut for OwnedRwLockWriteGuard<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds every permit.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized, F: Flavor> Drop for OwnedRwLockWriteGuard<T, F> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(MAX_READS);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::runtime::Builder;
+    use crate::sync::RwLock;
+    use crate::{executor, time};
+
+    #[test]
+    fn test_concurrent_readers() {
+        let lock = RwLock::new(5);
+        let a = lock.try_read().unwrap();
+        let b = lock.try_read().unwrap();
+        assert_eq!(10, *a + *b);
+        assert!(lock.try_write().is_err());
+        drop((a, b));
+        *lock.try_write().unwrap() += 1;
+        assert!(lock.try_read().is_ok());
+        assert_eq!(6, lock.into_inner());
+    }
+
+    #[test]
+    fn test_writer_is_not_starved() {
+        let order = Builder::simulation(0)
+            .run(async {
+                let lock = Rc::new(RwLock::new(()));
+                let order = Rc::new(RefCell::new(Vec::new()));
+                let reader = lock.clone().read_owned().await;
+
+                let writer = {
+                    let (lock, order) = (lock.clone(), order.clone());
+                    executor::spawn(async move {
+                        let _guard = lock.write().await;
+                        order.borrow_mut().push("write");
+                    })
+                };
+                time::sleep(Duration::from_millis(1)).await;
+
+                // The lock is only read locked, but the queued writer goes first.
+                assert!(lock.try_read().is_err());
+                let late_reader = {
+                    let (lock, order) = (lock.clone(), order.clone());
+                    executor::spawn(async move {
+                        let _guard = lock.read().await;
+                        order.borrow_mut().push("read");
+                    })
+                };
+                time::sleep(Duration::from_millis(1)).await;
+
+                drop(reader);
+                writer.await.unwrap();
+                late_reader.await.unwrap();
+                order.take()
+            })
+            .unwrap();
+        assert_eq!(vec!["write", "read"], order);
+    }
+}
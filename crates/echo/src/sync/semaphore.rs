@@ -0,0 +1,422 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Display;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use super::flavor::{self, Flavor, Local, Shared, StateCell};
+
+/// The waiter queue shared by every primitive in `sync`.
+pub struct State {
+    permits: usize,
+    waiters: VecDeque<Waiter>,
+    /// Waiters that have been handed their permits but not yet polled.
+    granted: HashSet<u64>,
+    next_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    permits: usize,
+    waker: Waker,
+}
+
+impl State {
+    fn new(permits: usize) -> Self {
+        Self {
+            permits,
+            waiters: VecDeque::new(),
+            granted: HashSet::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Takes permits without waiting. Fails if anyone is already queued, so
+    /// that waiters are served in order.
+    fn try_acquire(&mut self, permits: usize) -> bool {
+        if self.waiters.is_empty() && self.permits >= permits {
+            self.permits -= permits;
+            return true;
+        }
+        false
+    }
+
+    fn poll_acquire(
+        &mut self,
+        slot: &mut Option<u64>,
+        permits: usize,
+        cx: &Context<'_>,
+    ) -> Poll<()> {
+        let Some(id) = *slot else {
+            if self.try_acquire(permits) {
+                return Poll::Ready(());
+            }
+
+            let id = self.next_id;
+            self.next_id += 1;
+            self.waiters.push_back(Waiter {
+                id,
+                permits,
+                waker: cx.waker().clone(),
+            });
+            *slot = Some(id);
+            return Poll::Pending;
+        };
+
+        if self.granted.remove(&id) {
+            *slot = None;
+            return Poll::Ready(());
+        }
+
+        if let Some(waiter) = self.waiters.iter_mut().find(|w| w.id == id)
+            && !waiter.waker.will_wake(cx.waker())
+        {
+            waiter.waker = cx.waker().clone();
+        }
+        Poll::Pending
+    }
+
+    /// Returns permits and hands them to waiters from the front of the queue.
+    /// The returned wakers should be woken once the state is no longer locked.
+    fn release(&mut self, permits: usize) -> Vec<Waker> {
+        self.permits += permits;
+
+        let mut wakers = Vec::new();
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.permits > self.permits {
+                break;
+            }
+
+            let waiter = self.waiters.pop_front().unwrap();
+            self.permits -= waiter.permits;
+            self.granted.insert(waiter.id);
+            wakers.push(waiter.waker);
+        }
+        wakers
+    }
+
+    /// Gives up on an acquire that is being dropped, returning any permits it
+    /// was granted in the meantime.
+    fn cancel(&mut self, slot: &mut Option<u64>, permits: usize) -> Vec<Waker> {
+        let Some(id) = slot.take() else {
+            return Vec::new();
+        };
+
+        if self.granted.remove(&id) {
+            return self.release(permits);
+        }
+
+        // The waiter may have been holding up everyone behind it.
+        self.waiters.retain(|w| w.id != id);
+        self.release(0)
+    }
+}
+
+fn wake_all(wakers: Vec<Waker>) {
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+pub struct Semaphore<F: Flavor> {
+    state: F::Cell<State>,
+}
+
+impl<F: Flavor> Semaphore<F> {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            state: F::Cell::new(State::new(permits)),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.with(|s| s.permits)
+    }
+
+    /// Adds permits, waking waiters that can now proceed.
+    pub fn add_permits(&self, permits: usize) {
+        wake_all(self.state.with(|s| s.release(permits)));
+    }
+
+    pub fn acquire(&self) -> Acquire<'_, F> {
+        self.acquire_many(1)
+    }
+
+    /// Waits for `permits` permits at once. Waiters behind this one in the
+    /// queue are held up until it has been served.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_, F> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_, F>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(
+        &self,
+        permits: usize,
+    ) -> Result<SemaphorePermit<'_, F>, TryAcquireError> {
+        if self.state.with(|s| s.try_acquire(permits)) {
+            Ok(SemaphorePermit {
+                semaphore: self,
+                permits,
+            })
+        } else {
+            Err(TryAcquireError)
+        }
+    }
+
+    fn poll_acquire(
+        &self,
+        waiter: &mut Option<u64>,
+        permits: usize,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        let poll = self.state.with(|s| s.poll_acquire(waiter, permits, cx));
+        if poll.is_pending() {
+            flavor::record_wait::<F>(std::any::type_name::<Self>());
+        }
+        poll
+    }
+
+    fn cancel(&self, waiter: &mut Option<u64>, permits: usize) {
+        if waiter.is_some() {
+            wake_all(self.state.with(|s| s.cancel(waiter, permits)));
+        }
+    }
+
+    async fn acquire_owned_inner(this: F::Ptr<Self>, permits: usize) -> OwnedSemaphorePermit<F> {
+        this.acquire_many(permits).await.forget();
+        OwnedSemaphorePermit {
+            semaphore: this,
+            permits,
+        }
+    }
+
+    fn try_acquire_owned_inner(
+        this: F::Ptr<Self>,
+        permits: usize,
+    ) -> Result<OwnedSemaphorePermit<F>, TryAcquireError> {
+        this.try_acquire_many(permits)?.forget();
+        Ok(OwnedSemaphorePermit {
+            semaphore: this,
+            permits,
+        })
+    }
+}
+
+impl Semaphore<Local> {
+    /// Like [`Semaphore::acquire`], but the permit keeps the semaphore alive
+    /// instead of borrowing it, so it can be moved into another task.
+    pub async fn acquire_owned(self: Rc<Self>) -> OwnedSemaphorePermit<Local> {
+        Self::acquire_owned_inner(self, 1).await
+    }
+
+    pub async fn acquire_many_owned(self: Rc<Self>, permits: usize) -> OwnedSemaphorePermit<Local> {
+        Self::acquire_owned_inner(self, permits).await
+    }
+
+    pub fn try_acquire_owned(
+        self: Rc<Self>,
+    ) -> Result<OwnedSemaphorePermit<Local>, TryAcquireError> {
+        Self::try_acquire_owned_inner(self, 1)
+    }
+}
+
+impl Semaphore<Shared> {
+    /// Like [`Semaphore::acquire`], but the permit keeps the semaphore alive
+    /// instead of borrowing it, so it can be moved into another task.
+    pub async fn acquire_owned(self: Arc<Self>) -> OwnedSemaphorePermit<Shared> {
+        Self::acquire_owned_inner(self, 1).await
+    }
+
+    pub async fn acquire_many_owned(
+        self: Arc<Self>,
+        permits: usize,
+    ) -> OwnedSemaphorePermit<Shared> {
+        Self::acquire_owned_inner(self, permits).await
+    }
+
+    pub fn try_acquire_owned(
+        self: Arc<Self>,
+    ) -> Result<OwnedSemaphorePermit<Shared>, TryAcquireError> {
+        Self::try_acquire_owned_inner(self, 1)
+    }
+}
+
+pub struct Acquire<'a, F: Flavor> {
+    semaphore: &'a Semaphore<F>,
+    permits: usize,
+    waiter: Option<u64>,
+}
+
+impl<'a, F: Flavor> Future for Acquire<'a, F> {
+    type Output = SemaphorePermit<'a, F>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.semaphore
+            .poll_acquire(&mut this.waiter, this.permits, cx)
+            .map(|()| SemaphorePermit {
+                semaphore: this.semaphore,
+                permits: this.permits,
+            })
+    }
+}
+
+impl<F: Flavor> Drop for Acquire<'_, F> {
+    fn drop(&mut self) {
+        self.semaphore.cancel(&mut self.waiter, self.permits);
+    }
+}
+
+/// Permits borrowed from a [`Semaphore`], returned when dropped.
+pub struct SemaphorePermit<'a, F: Flavor> {
+    semaphore: &'a Semaphore<F>,
+    permits: usize,
+}
+
+impl<F: Flavor> SemaphorePermit<'_, F> {
+    /// Keeps the permits out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl<F: Flavor> Drop for SemaphorePermit<'_, F> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+pub struct OwnedSemaphorePermit<F: Flavor> {
+    semaphore: F::Ptr<Semaphore<F>>,
+    permits: usize,
+}
+
+impl<F: Flavor> OwnedSemaphorePermit<F> {
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+
+    pub fn semaphore(&self) -> &F::Ptr<Semaphore<F>> {
+        &self.semaphore
+    }
+}
+
+impl<F: Flavor> Drop for OwnedSemaphorePermit<F> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TryAcquireError;
+
+impl Display for TryAcquireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No permits available")
+    }
+}
+
+impl std::error::Error for TryAcquireError {}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::executor;
+    use crate::runtime::Builder;
+    use crate::sync::{Semaphore, TryAcquireError};
+
+    #[test]
+    fn test_try_acquire() {
+        let semaphore = Semaphore::new(2);
+        let a = semaphore.try_acquire().unwrap();
+        let _b = semaphore.try_acquire().unwrap();
+        assert_eq!(TryAcquireError, semaphore.try_acquire().err().unwrap());
+        drop(a);
+        assert_eq!(1, semaphore.available_permits());
+        semaphore.try_acquire_many(1).unwrap().forget();
+        assert_eq!(0, semaphore.available_permits());
+    }
+
+    #[test]
+    fn test_waiters_are_fifo() {
+        for seed in 0..10 {
+            let order = Builder::simulation(seed)
+                .run(async {
+                    let semaphore = Rc::new(Semaphore::new(0));
+                    let order = Rc::new(RefCell::new(Vec::new()));
+                    let mut handles = Vec::new();
+                    for i in 0..5 {
+                        let semaphore = semaphore.clone();
+                        let order = order.clone();
+                        handles.push(executor::spawn(async move {
+                            let _permit = semaphore.acquire_owned().await;
+                            order.borrow_mut().push(i);
+                        }));
+                        // Let the task queue up before spawning the next.
+                        crate::time::sleep(std::time::Duration::from_millis(1)).await;
+                    }
+
+                    semaphore.add_permits(1);
+                    for handle in handles {
+                        handle.await.unwrap();
+                    }
+                    order.take()
+                })
+                .unwrap();
+            assert_eq!(vec![0, 1, 2, 3, 4], order);
+        }
+    }
+
+    #[test]
+    fn test_large_acquire_blocks_later_waiters() {
+        Builder::simulation(0)
+            .run(async {
+                let semaphore = Rc::new(Semaphore::new(1));
+                let big = executor::spawn(semaphore.clone().acquire_many_owned(2));
+                crate::time::sleep(std::time::Duration::from_millis(1)).await;
+
+                // A permit is free, but the queued request for two comes first.
+                assert!(semaphore.try_acquire().is_err());
+                semaphore.add_permits(1);
+                let permit = big.await.unwrap();
+                assert_eq!(0, semaphore.available_permits());
+                drop(permit);
+                assert_eq!(2, semaphore.available_permits());
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_dropped_waiter_gives_way() {
+        Builder::simulation(0)
+            .run(async {
+                let semaphore = Rc::new(Semaphore::new(0));
+                let mut big = Box::pin(semaphore.acquire_many(5));
+                assert!(futures_poll(big.as_mut()).is_pending());
+                let small = executor::spawn(semaphore.clone().acquire_owned());
+                semaphore.add_permits(1);
+                drop(big);
+                small.await.unwrap();
+            })
+            .unwrap();
+    }
+
+    fn futures_poll<F: Future>(fut: std::pin::Pin<&mut F>) -> std::task::Poll<F::Output> {
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        fut.poll(&mut cx)
+    }
+}
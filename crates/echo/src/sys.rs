@@ -28,6 +28,25 @@ pub fn epoll_create() -> io::Result<RawFd> {
     syscall!(epoll_create1(0))
 }
 
+pub fn eventfd_create() -> io::Result<RawFd> {
+    syscall!(eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC))
+}
+
+/// Adds one to the eventfd's counter, making it readable.
+pub fn eventfd_signal(fd: RawFd) -> io::Result<()> {
+    let one: u64 = 1;
+    syscall!(write(fd, &one as *const u64 as *const libc::c_void, 8)).map(|_| ())
+}
+
+/// Resets the eventfd's counter. Fine to call when it isn't readable.
+pub fn eventfd_drain(fd: RawFd) -> io::Result<()> {
+    let mut count: u64 = 0;
+    match syscall!(read(fd, &mut count as *mut u64 as *mut libc::c_void, 8)) {
+        Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+        _ => Ok(()),
+    }
+}
+
 pub fn sock_listen(fd: RawFd, backlog: c_int) -> io::Result<i32> {
     syscall!(listen(fd, backlog))
 }
@@ -81,8 +100,7 @@ pub fn sockaddr_to_socketaddr(addr: libc::sockaddr) -> SocketAddr {
     match addr.sa_family as c_int {
         libc::AF_INET => {
             let sockaddr_in: libc::sockaddr_in = unsafe { std::mem::transmute(addr) };
-            let ip =
-                std::net::Ipv4Addr::from(u32::from_be(sockaddr_in.sin_addr.s_addr).to_ne_bytes());
+            let ip = std::net::Ipv4Addr::from(u32::from_be(sockaddr_in.sin_addr.s_addr));
             let port = u16::from_be(sockaddr_in.sin_port);
             SocketAddr::V4(std::net::SocketAddrV4::new(ip, port))
         }
@@ -100,7 +118,7 @@ pub fn socketaddr_to_addrinfo(addr: SocketAddr) -> libc::addrinfo {
                 sin_family: libc::AF_INET as u16,
                 sin_port: v4_addr.port().to_be(),
                 sin_addr: libc::in_addr {
-                    s_addr: u32::from_ne_bytes(v4_addr.ip().octets()).to_be(),
+                    s_addr: u32::from_ne_bytes(v4_addr.ip().octets()),
                 },
                 sin_zero: [0; 8],
             };
@@ -136,3 +154,226 @@ pub fn socketaddr_to_addrinfo(addr: SocketAddr) -> libc::addrinfo {
 pub fn close_socket(fd: RawFd) -> io::Result<i32> {
     syscall!(close(fd))
 }
+
+pub fn sock_connect(fd: RawFd, addr: SocketAddr) -> io::Result<i32> {
+    let (storage, len) = socketaddr_to_storage(addr);
+    syscall!(connect(
+        fd,
+        &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+        len
+    ))
+}
+
+/// Send on a socket without raising SIGPIPE if the peer has gone away.
+pub fn sock_send(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let res = syscall!(send(
+        fd,
+        buf.as_ptr() as *const _,
+        buf.len(),
+        libc::MSG_NOSIGNAL
+    ))?;
+    Ok(res as usize)
+}
+
+pub fn sock_recv(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let res = syscall!(recv(fd, buf.as_mut_ptr() as *mut _, buf.len(), 0))?;
+    Ok(res as usize)
+}
+
+/// Gathers `bufs` into a single send. This is `writev` with `MSG_NOSIGNAL`,
+/// which `writev` itself has no way to pass.
+pub fn sock_send_vectored(fd: RawFd, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    // `IoSlice` is guaranteed to be ABI compatible with `iovec`.
+    msg.msg_iov = bufs.as_ptr() as *mut libc::iovec;
+    msg.msg_iovlen = bufs.len().min(libc::UIO_MAXIOV as usize);
+    let res = syscall!(sendmsg(fd, &msg, libc::MSG_NOSIGNAL))?;
+    Ok(res as usize)
+}
+
+/// Scatters what is read across `bufs`, filling each before the next.
+pub fn readv(fd: RawFd, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+    let count = bufs.len().min(libc::UIO_MAXIOV as usize) as c_int;
+    let res = syscall!(readv(fd, bufs.as_mut_ptr() as *mut libc::iovec, count))?;
+    Ok(res as usize)
+}
+
+pub fn read(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let res = syscall!(read(fd, buf.as_mut_ptr() as *mut _, buf.len()))?;
+    Ok(res as usize)
+}
+
+pub fn write(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let res = syscall!(write(fd, buf.as_ptr() as *const _, buf.len()))?;
+    Ok(res as usize)
+}
+
+/// Opens a non-blocking pipe, returning the read end and then the write end.
+pub fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    syscall!(pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC))?;
+    Ok((fds[0], fds[1]))
+}
+
+/// Moves up to `len` bytes between two fds without copying them through
+/// userspace. One of them must be a pipe.
+pub fn splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> io::Result<usize> {
+    let res = syscall!(splice(
+        fd_in,
+        std::ptr::null_mut(),
+        fd_out,
+        std::ptr::null_mut(),
+        len,
+        libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK
+    ))?;
+    Ok(res as usize)
+}
+
+/// Sends up to `len` bytes from the current position of the file `fd_in`.
+pub fn sendfile(fd_out: RawFd, fd_in: RawFd, len: usize) -> io::Result<usize> {
+    let res = syscall!(sendfile(fd_out, fd_in, std::ptr::null_mut(), len))?;
+    Ok(res as usize)
+}
+
+pub fn is_regular_file(fd: RawFd) -> io::Result<bool> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    syscall!(fstat(fd, &mut stat))?;
+    Ok(stat.st_mode & libc::S_IFMT == libc::S_IFREG)
+}
+
+/// A file's device and inode, which are the same for every fd sharing it.
+pub type FileId = (u64, u64);
+
+pub fn file_id(fd: RawFd) -> io::Result<FileId> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    syscall!(fstat(fd, &mut stat))?;
+    Ok((stat.st_dev, stat.st_ino))
+}
+
+pub fn get_fd_flags(fd: RawFd) -> io::Result<c_int> {
+    syscall!(fcntl(fd, libc::F_GETFL))
+}
+
+pub fn set_fd_flags(fd: RawFd, flags: c_int) -> io::Result<()> {
+    syscall!(fcntl(fd, libc::F_SETFL, flags)).map(|_| ())
+}
+
+pub fn sock_shutdown(fd: RawFd, how: std::net::Shutdown) -> io::Result<i32> {
+    let how = match how {
+        std::net::Shutdown::Read => libc::SHUT_RD,
+        std::net::Shutdown::Write => libc::SHUT_WR,
+        std::net::Shutdown::Both => libc::SHUT_RDWR,
+    };
+    syscall!(shutdown(fd, how))
+}
+
+/// Returns and clears the pending error on a socket, e.g. the outcome of a
+/// non-blocking connect.
+pub fn sock_take_error(fd: RawFd) -> io::Result<Option<io::Error>> {
+    let mut err: c_int = 0;
+    let mut len = std::mem::size_of::<c_int>() as libc::socklen_t;
+    syscall!(getsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_ERROR,
+        &mut err as *mut c_int as *mut _,
+        &mut len,
+    ))?;
+
+    if err == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(io::Error::from_raw_os_error(err)))
+    }
+}
+
+pub fn sock_local_addr(fd: RawFd) -> io::Result<SocketAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    syscall!(getsockname(
+        fd,
+        &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+        &mut len
+    ))?;
+    storage_to_socketaddr(&storage)
+}
+
+pub fn sock_peer_addr(fd: RawFd) -> io::Result<SocketAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    syscall!(getpeername(
+        fd,
+        &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+        &mut len
+    ))?;
+    storage_to_socketaddr(&storage)
+}
+
+pub fn socketaddr_to_storage(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(v4_addr) => {
+            let sockaddr_in = libc::sockaddr_in {
+                sin_family: libc::AF_INET as u16,
+                sin_port: v4_addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(v4_addr.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                std::ptr::write(
+                    &mut storage as *mut _ as *mut libc::sockaddr_in,
+                    sockaddr_in,
+                )
+            };
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6_addr) => {
+            let sockaddr_in6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as u16,
+                sin6_port: v6_addr.port().to_be(),
+                sin6_flowinfo: v6_addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: v6_addr.ip().octets(),
+                },
+                sin6_scope_id: v6_addr.scope_id(),
+            };
+            unsafe {
+                std::ptr::write(
+                    &mut storage as *mut _ as *mut libc::sockaddr_in6,
+                    sockaddr_in6,
+                )
+            };
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
+pub fn storage_to_socketaddr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match storage.ss_family as c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = std::net::Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            let port = u16::from_be(addr.sin_port);
+            Ok(SocketAddr::V4(std::net::SocketAddrV4::new(ip, port)))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = std::net::Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            let port = u16::from_be(addr.sin6_port);
+            Ok(SocketAddr::V6(std::net::SocketAddrV6::new(
+                ip,
+                port,
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unsupported address family",
+        )),
+    }
+}
@@ -1,60 +1,724 @@
-use std::io;
-use std::net::SocketAddr;
-use std::os::fd::{AsRawFd, FromRawFd};
+use std::cell::RefCell;
+use std::future::poll_fn;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::net::{Shutdown, SocketAddr};
+use std::os::fd::FromRawFd;
 use std::os::unix::prelude::RawFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
-use crate::{reactor, sys};
+use crate::io::{AsyncRead, AsyncWrite};
+use crate::reactor::{self, Direction};
+use crate::sim::net;
+use crate::stream::Stream;
+use crate::{sim, sys};
+
+mod split;
+
+pub use self::split::{OwnedReadHalf, OwnedWriteHalf, ReadHalf, ReuniteError, WriteHalf};
+
+/// Listeners and streams are backed by real sockets, or by the in-memory
+/// network when running under a simulation.
+enum ListenerInner {
+    Sys(RawFd),
+    Sim(net::Listener),
+}
 
 pub struct TcpListener {
-    fd: RawFd,
+    inner: ListenerInner,
 }
 
 impl TcpListener {
     pub fn bind(addr: SocketAddr, backlog_size: i32) -> io::Result<Self> {
+        if sim::is_enabled() {
+            let listener = net::Listener::bind(addr, backlog_size)?;
+            return Ok(Self {
+                inner: ListenerInner::Sim(listener),
+            });
+        }
+
         let fd = sys::open_tcp_socket(addr)?;
+        let listener = Self {
+            inner: ListenerInner::Sys(fd),
+        };
         sys::sock_bind(fd, addr, true)?;
         sys::sock_listen(fd, backlog_size)?;
 
         reactor::register_interest(fd, libc::EPOLLIN)?;
-        Ok(Self { fd })
+        Ok(listener)
     }
 
-    pub fn accept(&self) -> AcceptFuture {
-        AcceptFuture { fd: self.fd }
+    pub fn accept(&self) -> AcceptFuture<'_> {
+        AcceptFuture { listener: self }
+    }
+
+    /// Accepts connections for as long as the stream is polled.
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        let fd = match &self.inner {
+            ListenerInner::Sys(fd) => *fd,
+            ListenerInner::Sim(listener) => {
+                return listener.poll_accept(cx).map_ok(|(stream, addr)| {
+                    let stream = TcpStream {
+                        inner: StreamInner::Sim(RefCell::new(stream)),
+                    };
+                    (stream, addr)
+                });
+            }
+        };
+
+        if reactor::completes_io() {
+            return reactor::poll_accept(fd, cx)
+                .map(|res| res.and_then(|(new_fd, addr)| Ok((TcpStream::from_fd(new_fd)?, addr))));
+        }
+
+        match sys::sock_accept_nonblock(fd) {
+            Ok((new_fd, addr)) => Poll::Ready(TcpStream::from_fd(new_fd).map(|s| (s, addr))),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor::register_wake(fd, Direction::Read, cx.waker().clone())?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.inner {
+            ListenerInner::Sys(fd) => sys::sock_local_addr(*fd),
+            ListenerInner::Sim(listener) => Ok(listener.local_addr()),
+        }
+    }
+
+    /// The listening socket's file descriptor. Fails with `Unsupported` under
+    /// a simulation, where there isn't one.
+    pub fn raw_fd(&self) -> io::Result<RawFd> {
+        match &self.inner {
+            ListenerInner::Sys(fd) => Ok(*fd),
+            ListenerInner::Sim(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
     }
 }
 
 impl FromRawFd for TcpListener {
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self { fd }
+        Self {
+            inner: ListenerInner::Sys(fd),
+        }
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        if let ListenerInner::Sys(fd) = self.inner {
+            let _ = reactor::deregister(fd);
+            let _ = sys::close_socket(fd);
+        }
+    }
+}
+
+pub struct AcceptFuture<'a> {
+    listener: &'a TcpListener,
+}
+
+impl Future for AcceptFuture<'_> {
+    type Output = io::Result<(TcpStream, SocketAddr)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.listener.poll_accept(cx)
     }
 }
 
-impl AsRawFd for TcpListener {
-    fn as_raw_fd(&self) -> RawFd {
-        self.fd.clone()
+/// The connections accepted by a [`TcpListener`], from
+/// [`TcpListener::incoming`]. Never ends.
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+}
+
+impl Stream for Incoming<'_> {
+    type Item = io::Result<TcpStream>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.listener
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _)| stream)))
     }
 }
 
-pub struct AcceptFuture {
-    fd: RawFd,
+enum StreamInner {
+    Sys(RawFd),
+    /// Borrowed only for the length of each operation, so the halves of a
+    /// split stream can share it.
+    Sim(RefCell<net::Stream>),
 }
 
-impl Future for AcceptFuture {
-    type Output = Result<(RawFd, SocketAddr), std::io::Error>;
+pub struct TcpStream {
+    inner: StreamInner,
+}
+
+impl TcpStream {
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        if sim::is_enabled() {
+            let stream = net::connect(addr).await?;
+            return Ok(Self {
+                inner: StreamInner::Sim(RefCell::new(stream)),
+            });
+        }
+
+        let fd = sys::open_tcp_socket(addr)?;
+        let stream = match sys::sock_connect(fd, addr) {
+            Ok(_) => Self::from_fd(fd)?,
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => Self::from_fd(fd)?,
+            Err(e) => {
+                let _ = sys::close_socket(fd);
+                return Err(e);
+            }
+        };
+
+        poll_fn(|cx| stream.poll_connected(cx)).await?;
+        Ok(stream)
+    }
 
-    fn poll(
-        self: std::pin::Pin<&mut Self>,
-        ctx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        match sys::sock_accept_nonblock(self.fd) {
-            Ok((new_fd, addr)) => std::task::Poll::Ready(Ok((new_fd, addr))),
+    /// Takes ownership of a connected, non-blocking socket.
+    fn from_fd(fd: RawFd) -> io::Result<Self> {
+        let stream = Self {
+            inner: StreamInner::Sys(fd),
+        };
+        reactor::register_interest(fd, reactor::STREAM_INTEREST)?;
+        Ok(stream)
+    }
+
+    fn poll_connected(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let StreamInner::Sys(fd) = self.inner else {
+            return Poll::Ready(Ok(()));
+        };
+
+        if let Some(e) = sys::sock_take_error(fd)? {
+            return Poll::Ready(Err(e));
+        }
+
+        // Once a non-blocking connect completes the socket has a peer.
+        match sys::sock_peer_addr(fd) {
+            Ok(_) => Poll::Ready(Ok(())),
+            Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => {
+                reactor::register_wake(fd, Direction::Write, cx.waker().clone())?;
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    /// The socket's file descriptor. Fails with `Unsupported` under a
+    /// simulation, where there isn't one.
+    pub fn raw_fd(&self) -> io::Result<RawFd> {
+        match &self.inner {
+            StreamInner::Sys(fd) => Ok(*fd),
+            StreamInner::Sim(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.inner {
+            StreamInner::Sys(fd) => sys::sock_local_addr(*fd),
+            StreamInner::Sim(stream) => Ok(stream.borrow().local_addr()),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match &self.inner {
+            StreamInner::Sys(fd) => sys::sock_peer_addr(*fd),
+            StreamInner::Sim(stream) => Ok(stream.borrow().peer_addr()),
+        }
+    }
+
+    pub fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        self.shutdown_inner(how)
+    }
+
+    /// Splits the stream into halves that borrow it, so it can be read from
+    /// and written to at the same time.
+    pub fn split(&mut self) -> (ReadHalf<'_>, WriteHalf<'_>) {
+        split::split(self)
+    }
+
+    /// Splits the stream into halves that can each be moved into their own
+    /// task. [`OwnedReadHalf::reunite`] puts them back together.
+    pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        split::split_owned(self)
+    }
+
+    // The IO operations only need `&self`, so that the split halves can share
+    // the stream. The trait impls and the halves all go through these.
+
+    fn shutdown_inner(&self, how: Shutdown) -> io::Result<()> {
+        match &self.inner {
+            StreamInner::Sys(fd) => sys::sock_shutdown(*fd, how).map(|_| ()),
+            StreamInner::Sim(stream) => {
+                stream.borrow_mut().shutdown(how);
+                Ok(())
+            }
+        }
+    }
+
+    fn poll_read_inner(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let fd = match &self.inner {
+            StreamInner::Sys(fd) => *fd,
+            StreamInner::Sim(stream) => return stream.borrow_mut().poll_read(cx, buf),
+        };
+        if reactor::completes_io() {
+            return reactor::poll_recv(fd, cx, &mut [IoSliceMut::new(buf)]);
+        }
+
+        match sys::sock_recv(fd, buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor::register_wake(fd, Direction::Read, cx.waker().clone())?;
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        }
+    }
+
+    fn poll_read_vectored_inner(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let fd = match &self.inner {
+            StreamInner::Sys(fd) => *fd,
+            StreamInner::Sim(stream) => {
+                let buf = bufs
+                    .iter_mut()
+                    .find(|b| !b.is_empty())
+                    .map_or(&mut [][..], |b| &mut **b);
+                return stream.borrow_mut().poll_read(cx, buf);
+            }
+        };
+        if reactor::completes_io() {
+            return reactor::poll_recv(fd, cx, bufs);
+        }
+
+        match sys::readv(fd, bufs) {
             Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                let waker = ctx.waker().clone().into();
-                reactor::register_wake(self.fd, waker).unwrap();
-                std::task::Poll::Pending
+                reactor::register_wake(fd, Direction::Read, cx.waker().clone())?;
+                Poll::Pending
             }
-            Err(e) => Err(e).into(),
+            res => Poll::Ready(res),
         }
     }
+
+    fn poll_write_inner(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let fd = match &self.inner {
+            StreamInner::Sys(fd) => *fd,
+            StreamInner::Sim(stream) => return stream.borrow_mut().poll_write(cx, buf),
+        };
+        if reactor::completes_io() {
+            return reactor::poll_send(fd, cx, &[IoSlice::new(buf)]);
+        }
+
+        match sys::sock_send(fd, buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor::register_wake(fd, Direction::Write, cx.waker().clone())?;
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        }
+    }
+
+    fn poll_write_vectored_inner(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let fd = match &self.inner {
+            StreamInner::Sys(fd) => *fd,
+            StreamInner::Sim(stream) => {
+                let buf = bufs
+                    .iter()
+                    .find(|b| !b.is_empty())
+                    .map_or(&[][..], |b| &**b);
+                return stream.borrow_mut().poll_write(cx, buf);
+            }
+        };
+        if reactor::completes_io() {
+            return reactor::poll_send(fd, cx, bufs);
+        }
+
+        match sys::sock_send_vectored(fd, bufs) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor::register_wake(fd, Direction::Write, cx.waker().clone())?;
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        }
+    }
+
+    /// Only real sockets gather; simulated streams write one buffer at a time.
+    fn is_write_vectored_inner(&self) -> bool {
+        matches!(self.inner, StreamInner::Sys(_))
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_read_inner(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_read_vectored_inner(cx, bufs)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_inner(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored_inner(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.is_write_vectored_inner()
+    }
+
+    /// Writes go straight to the socket, so there is nothing to flush.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.shutdown_inner(Shutdown::Write))
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if let StreamInner::Sys(fd) = self.inner {
+            let _ = reactor::deregister(fd);
+            let _ = sys::close_socket(fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::poll_fn;
+    use std::pin::pin;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::executor;
+    use crate::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::reactor::DriverKind;
+    use crate::runtime::{self, Builder};
+    use crate::sim::net::NetConfig;
+    use crate::stream::StreamExt;
+    use crate::time;
+
+    /// Sends `payload` to an echo server over a fresh connection, returning
+    /// what came back.
+    async fn echo_round_trip(payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+        let addr = listener.local_addr()?;
+        let server = executor::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await?;
+            stream.write_all(&data).await?;
+            AsyncWriteExt::shutdown(&mut stream).await
+        });
+
+        let mut client = TcpStream::connect(addr).await?;
+        client.write_all(&payload).await?;
+        client.shutdown(Shutdown::Write)?;
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await?;
+        server.await.unwrap()?;
+        Ok(echoed)
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..200_000u32).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_real_socket_round_trip() {
+        let echoed = runtime::run(echo_round_trip(payload())).unwrap().unwrap();
+        assert_eq!(payload(), echoed);
+    }
+
+    /// Writes two buffers with one call and reads them back into two more.
+    async fn vectored_round_trip() -> io::Result<([u8; 4], [u8; 4])> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (mut server, _) = listener.accept().await?;
+
+        assert!(client.is_write_vectored());
+        let bufs = [IoSlice::new(b"head"), IoSlice::new(b"body")];
+        assert_eq!(8, client.write_vectored(&bufs).await?);
+
+        let (mut head, mut body) = ([0u8; 4], [0u8; 4]);
+        let mut read = 0;
+        while read < 8 {
+            let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body)];
+            let mut bufs = &mut bufs[..];
+            IoSliceMut::advance_slices(&mut bufs, read);
+            read += server.read_vectored(bufs).await?;
+        }
+        Ok((head, body))
+    }
+
+    #[test]
+    fn test_real_socket_vectored() {
+        let (head, body) = runtime::run(vectored_round_trip()).unwrap().unwrap();
+        assert_eq!((*b"head", *b"body"), (head, body));
+    }
+
+    #[test]
+    fn test_io_uring_round_trip() {
+        let echoed = Builder::new()
+            .driver(DriverKind::IoUring)
+            .run(echo_round_trip(payload()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload(), echoed);
+    }
+
+    #[test]
+    fn test_io_uring_vectored() {
+        let (head, body) = Builder::new()
+            .driver(DriverKind::IoUring)
+            .run(vectored_round_trip())
+            .unwrap()
+            .unwrap();
+        assert_eq!((*b"head", *b"body"), (head, body));
+    }
+
+    /// Fills the client's send buffer, abandons a write that couldn't go
+    /// through, and then writes something else, returning how much was
+    /// written before the abandoned write and everything the server received.
+    async fn abandoned_write() -> io::Result<(usize, Vec<u8>)> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (mut server, _) = listener.accept().await?;
+
+        let mut written = 0;
+        loop {
+            let res =
+                poll_fn(|cx| Poll::Ready(Pin::new(&mut client).poll_write(cx, &[b'a'; 4096])))
+                    .await;
+            match res {
+                Poll::Ready(n) => written += n?,
+                Poll::Pending => break,
+            }
+        }
+        let abandoned =
+            poll_fn(|cx| Poll::Ready(Pin::new(&mut client).poll_write(cx, b"abandoned"))).await;
+        assert!(abandoned.is_pending());
+
+        let reader = executor::spawn(async move {
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).await.map(|_| received)
+        });
+        client.write_all(b"last").await?;
+        client.shutdown(Shutdown::Write)?;
+        Ok((written, reader.await.unwrap()?))
+    }
+
+    #[test]
+    fn test_abandoned_writes_send_nothing() {
+        let (written, received) = runtime::run(abandoned_write()).unwrap().unwrap();
+        let mut expected = vec![b'a'; written];
+        expected.extend_from_slice(b"last");
+        assert!(expected == received, "sent abandoned data");
+    }
+
+    #[test]
+    fn test_io_uring_abandoned_write_is_reported_next() {
+        let (resumed_at, received) = Builder::new()
+            .driver(DriverKind::IoUring)
+            .run(async {
+                let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+                let mut client = TcpStream::connect(listener.local_addr()?).await?;
+                let (mut server, _) = listener.accept().await?;
+
+                // Give up on a `write_all` as soon as its first send is in flight.
+                let data = payload();
+                {
+                    let mut write_all = pin!(client.write_all(&data));
+                    let first = poll_fn(|cx| Poll::Ready(write_all.as_mut().poll(cx))).await;
+                    assert!(first.is_pending());
+                }
+
+                let reader = executor::spawn(async move {
+                    let mut received = Vec::new();
+                    server.read_to_end(&mut received).await.map(|_| received)
+                });
+                // The send went ahead anyway, and carrying on with the same data
+                // picks up where it left off.
+                let resumed_at = client.write(&data).await?;
+                client.write_all(&data[resumed_at..]).await?;
+                client.shutdown(Shutdown::Write)?;
+                io::Result::Ok((resumed_at, reader.await.unwrap()?))
+            })
+            .unwrap()
+            .unwrap();
+        assert!(resumed_at > 0);
+        assert!(payload() == received, "lost or repeated the abandoned send");
+    }
+
+    #[test]
+    fn test_sim_round_trip() {
+        let echoed = Builder::simulation(1)
+            .run(echo_round_trip(payload()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload(), echoed);
+    }
+
+    #[test]
+    fn test_sim_round_trip_with_faults() {
+        let config = NetConfig {
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            loss: 0.1,
+            partial_writes: 0.5,
+            would_block: 0.2,
+            buffer_size: 4096,
+            ..NetConfig::default()
+        };
+
+        for seed in 0..10 {
+            let echoed = Builder::simulation(seed)
+                .network(config.clone())
+                .run(echo_round_trip(payload()))
+                .unwrap()
+                .unwrap();
+            assert_eq!(payload(), echoed);
+        }
+    }
+
+    #[test]
+    fn test_sim_latency() {
+        let config = NetConfig {
+            latency: Duration::from_millis(50),
+            ..NetConfig::default()
+        };
+
+        let elapsed = Builder::simulation(0)
+            .network(config)
+            .run(async {
+                let start = time::now();
+                echo_round_trip(b"hello".to_vec()).await.unwrap();
+                time::now() - start
+            })
+            .unwrap();
+
+        // Handshake, request and response each take a round trip or a one-way
+        // trip; the FINs travel alongside the data.
+        assert_eq!(Duration::from_millis(200), elapsed);
+    }
+
+    #[test]
+    fn test_sim_reset() {
+        let config = NetConfig {
+            reset: 1.0,
+            ..NetConfig::default()
+        };
+
+        let err = Builder::simulation(0)
+            .network(config)
+            .run(echo_round_trip(payload()))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionReset, err.kind());
+    }
+
+    #[test]
+    fn test_incoming() {
+        let peers = Builder::simulation(2)
+            .run(async {
+                let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+                let addr = listener.local_addr()?;
+                let clients: Vec<_> = (0..3)
+                    .map(|_| executor::spawn(TcpStream::connect(addr)))
+                    .collect();
+
+                let mut peers = Vec::new();
+                let mut incoming = listener.incoming().take(3);
+                while let Some(stream) = incoming.next().await {
+                    peers.push(stream?.peer_addr()?);
+                }
+                for client in clients {
+                    client.await.unwrap()?;
+                }
+                io::Result::Ok(peers)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(3, peers.len());
+    }
+
+    #[test]
+    fn test_sim_connection_refused() {
+        let err = Builder::simulation(0)
+            .run(TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], 9))))
+            .unwrap()
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::ConnectionRefused, err.kind());
+    }
+
+    #[test]
+    fn test_sim_sockets_have_no_fd() {
+        let kinds = Builder::simulation(0)
+            .run(async {
+                let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+                let stream = TcpStream::connect(listener.local_addr()?).await?;
+                let kinds = [listener.raw_fd(), stream.raw_fd()].map(|fd| fd.unwrap_err().kind());
+                io::Result::Ok(kinds)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!([io::ErrorKind::Unsupported; 2], kinds);
+    }
+
+    #[test]
+    fn test_sim_is_deterministic() {
+        let run = |seed| {
+            let config = NetConfig {
+                jitter: Duration::from_millis(10),
+                partial_writes: 0.5,
+                ..NetConfig::default()
+            };
+            Builder::simulation(seed)
+                .network(config)
+                .run(async {
+                    let start = time::now();
+                    echo_round_trip(payload()).await.unwrap();
+                    time::now() - start
+                })
+                .unwrap()
+        };
+
+        assert_eq!(run(4), run(4));
+    }
 }
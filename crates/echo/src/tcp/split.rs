@@ -0,0 +1,305 @@
+use std::fmt;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::net::{Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use super::TcpStream;
+use crate::io::{AsyncRead, AsyncWrite};
+
+pub(super) fn split(stream: &mut TcpStream) -> (ReadHalf<'_>, WriteHalf<'_>) {
+    (ReadHalf(stream), WriteHalf(stream))
+}
+
+pub(super) fn split_owned(stream: TcpStream) -> (OwnedReadHalf, OwnedWriteHalf) {
+    let stream = Rc::new(stream);
+    let write = OwnedWriteHalf {
+        stream: stream.clone(),
+        shutdown_on_drop: true,
+    };
+    (OwnedReadHalf { stream }, write)
+}
+
+/// The read half of a [`TcpStream`], from [`TcpStream::split`].
+pub struct ReadHalf<'a>(&'a TcpStream);
+
+/// The write half of a [`TcpStream`], from [`TcpStream::split`]. Shutting it
+/// down shuts down writing on the whole stream.
+pub struct WriteHalf<'a>(&'a TcpStream);
+
+impl AsyncRead for ReadHalf<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.poll_read_inner(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.0.poll_read_vectored_inner(cx, bufs)
+    }
+}
+
+impl AsyncWrite for WriteHalf<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.poll_write_inner(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.0.poll_write_vectored_inner(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored_inner()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.0.shutdown_inner(Shutdown::Write))
+    }
+}
+
+/// The read half of a [`TcpStream`], from [`TcpStream::into_split`].
+pub struct OwnedReadHalf {
+    stream: Rc<TcpStream>,
+}
+
+/// The write half of a [`TcpStream`], from [`TcpStream::into_split`].
+///
+/// Dropping it shuts down writing on the stream, so the peer sees the end of
+/// the stream even while the read half carries on.
+pub struct OwnedWriteHalf {
+    stream: Rc<TcpStream>,
+    shutdown_on_drop: bool,
+}
+
+impl OwnedReadHalf {
+    /// Puts the stream back together, failing if `other` came from a
+    /// different stream.
+    pub fn reunite(self, other: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
+        reunite(self, other)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl OwnedWriteHalf {
+    /// Puts the stream back together, failing if `other` came from a
+    /// different stream.
+    pub fn reunite(self, other: OwnedReadHalf) -> Result<TcpStream, ReuniteError> {
+        reunite(other, self)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+fn reunite(read: OwnedReadHalf, mut write: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
+    if !Rc::ptr_eq(&read.stream, &write.stream) {
+        return Err(ReuniteError(read, write));
+    }
+
+    write.shutdown_on_drop = false;
+    drop(write);
+    Ok(Rc::into_inner(read.stream).expect("both halves have been given up"))
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.stream.poll_read_inner(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.stream.poll_read_vectored_inner(cx, bufs)
+    }
+}
+
+impl AsyncWrite for OwnedWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.stream.poll_write_inner(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.stream.poll_write_vectored_inner(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored_inner()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.stream.shutdown_inner(Shutdown::Write))
+    }
+}
+
+impl Drop for OwnedWriteHalf {
+    fn drop(&mut self) {
+        if self.shutdown_on_drop {
+            let _ = self.stream.shutdown_inner(Shutdown::Write);
+        }
+    }
+}
+
+/// The halves passed to `reunite` came from different streams. Holds them
+/// both, so they aren't lost.
+pub struct ReuniteError(pub OwnedReadHalf, pub OwnedWriteHalf);
+
+impl fmt::Debug for ReuniteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for ReuniteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tried to reunite halves of different streams")
+    }
+}
+
+impl std::error::Error for ReuniteError {}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::executor;
+    use crate::io::{self as echo_io, AsyncReadExt, AsyncWriteExt};
+    use crate::runtime::{self, Builder};
+    use crate::tcp::TcpListener;
+
+    async fn connected_pair() -> io::Result<(TcpStream, TcpStream)> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16)?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+        Ok((client, server))
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..200_000u32).map(|i| i as u8).collect()
+    }
+
+    /// Echoes a payload too big for the socket buffers, with each end reading
+    /// and writing from separate tasks. This only finishes if readers and
+    /// writers on the same socket are woken independently.
+    async fn echo_with_owned_halves() -> io::Result<Vec<u8>> {
+        let (client, server) = connected_pair().await?;
+        let (mut server_rd, mut server_wr) = server.into_split();
+        let echo =
+            executor::spawn(async move { echo_io::copy(&mut server_rd, &mut server_wr).await });
+
+        let (mut client_rd, mut client_wr) = client.into_split();
+        let writer = executor::spawn(async move { client_wr.write_all(&payload()).await });
+        let mut echoed = Vec::new();
+        client_rd.read_to_end(&mut echoed).await?;
+        writer.await.unwrap()?;
+        echo.await.unwrap()?;
+        Ok(echoed)
+    }
+
+    #[test]
+    fn test_owned_halves_real_socket() {
+        let echoed = runtime::run(echo_with_owned_halves()).unwrap().unwrap();
+        assert_eq!(payload(), echoed);
+    }
+
+    #[test]
+    fn test_owned_halves_sim() {
+        for seed in 0..5 {
+            let echoed = Builder::simulation(seed)
+                .run(echo_with_owned_halves())
+                .unwrap()
+                .unwrap();
+            assert_eq!(payload(), echoed);
+        }
+    }
+
+    #[test]
+    fn test_borrowed_halves() {
+        let echoed = runtime::run(async {
+            let (mut client, mut server) = connected_pair().await?;
+            let echo = executor::spawn(async move {
+                let (mut rd, mut wr) = server.split();
+                echo_io::copy(&mut rd, &mut wr).await
+            });
+
+            let (mut rd, mut wr) = client.split();
+            wr.write_all(b"hello").await?;
+            wr.shutdown().await?;
+            let mut echoed = Vec::new();
+            rd.read_to_end(&mut echoed).await?;
+            echo.await.unwrap()?;
+            io::Result::Ok(echoed)
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(b"hello", &echoed[..]);
+    }
+
+    #[test]
+    fn test_reunite() {
+        runtime::run(async {
+            let (a, b) = connected_pair().await.unwrap();
+            let (a_rd, a_wr) = a.into_split();
+            let (b_rd, b_wr) = b.into_split();
+
+            let Err(ReuniteError(a_rd, b_wr)) = a_rd.reunite(b_wr) else {
+                panic!("reunited halves of different streams");
+            };
+            let mut a = a_rd.reunite(a_wr).unwrap();
+            drop((b_rd, b_wr));
+
+            // Reuniting didn't shut the stream down.
+            a.write_all(b"still open").await.unwrap();
+        })
+        .unwrap();
+    }
+}
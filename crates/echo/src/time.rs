@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::TIMERS;
+use crate::executor::{self, Waiting};
+
+/// Returns the current time according to the runtime's clock. Under a
+/// simulation this is virtual time, which only moves forward when every task is
+/// idle.
+pub fn now() -> Instant {
+    TIMERS.with_borrow(|timers| match timers {
+        Some(timers) => timers.now(),
+        None => Instant::now(),
+    })
+}
+
+/// Waits until `duration` has elapsed.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(now() + duration)
+}
+
+/// Waits until `deadline` has been reached.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        entry: None,
+    }
+}
+
+pub struct Sleep {
+    deadline: Instant,
+    entry: Option<TimerKey>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let deadline = self.deadline;
+        let entry = self.entry;
+        let (ready, entry) = local_timers(|timers| {
+            if timers.now() >= deadline {
+                if let Some(key) = entry {
+                    timers.entries.remove(&key);
+                }
+                return (true, None);
+            }
+
+            let key = entry.unwrap_or_else(|| timers.next_key(deadline));
+            timers.entries.insert(key, cx.waker().clone());
+            (false, Some(key))
+        });
+
+        self.entry = entry;
+        if ready {
+            return Poll::Ready(());
+        }
+
+        executor::record_wait(Waiting::Timer { deadline });
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        let Some(key) = self.entry else {
+            return;
+        };
+
+        // The runtime may already have been torn down if the task owning this
+        // timer is dropped along with the executor.
+        TIMERS.with_borrow_mut(|timers| {
+            if let Some(timers) = timers {
+                timers.entries.remove(&key);
+            }
+        });
+    }
+}
+
+fn local_timers<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut Timers) -> T,
+{
+    TIMERS.with_borrow_mut(|timers| {
+        let timers = timers.as_mut().expect("Timers not started on this thread");
+        f(timers)
+    })
+}
+
+/// Returns the earliest pending deadline, if any.
+pub(crate) fn next_deadline() -> Option<Instant> {
+    local_timers(|timers| timers.entries.keys().next().map(|(deadline, _)| *deadline))
+}
+
+/// Wakes every timer whose deadline has passed.
+pub(crate) fn fire_expired() {
+    let wakers = local_timers(|timers| timers.take_expired());
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+/// Moves the virtual clock forward to the next pending deadline and wakes the
+/// timers that expire. Returns false if there was nothing to advance to.
+pub(crate) fn advance_to_next_deadline() -> bool {
+    let Some(deadline) = next_deadline() else {
+        return false;
+    };
+
+    local_timers(|timers| timers.advance_to(deadline));
+    fire_expired();
+    true
+}
+
+/// Converts the time until the next deadline into an `epoll_wait` timeout in
+/// milliseconds, rounding up so we never wake early. Returns -1 when there are
+/// no timers.
+pub(crate) fn epoll_timeout() -> i32 {
+    let Some(deadline) = next_deadline() else {
+        return -1;
+    };
+
+    let remaining = deadline.saturating_duration_since(now());
+    let millis = remaining.as_nanos().div_ceil(1_000_000);
+    millis.min(i32::MAX as u128) as i32
+}
+
+type TimerKey = (Instant, u64);
+
+pub(crate) enum Clock {
+    Real,
+    Virtual(Instant),
+}
+
+pub(crate) struct Timers {
+    clock: Clock,
+    entries: BTreeMap<TimerKey, Waker>,
+    next_id: u64,
+}
+
+impl Timers {
+    pub(crate) fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            entries: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn now(&self) -> Instant {
+        match self.clock {
+            Clock::Real => Instant::now(),
+            Clock::Virtual(now) => now,
+        }
+    }
+
+    fn next_key(&mut self, deadline: Instant) -> TimerKey {
+        let id = self.next_id;
+        self.next_id += 1;
+        (deadline, id)
+    }
+
+    fn advance_to(&mut self, deadline: Instant) {
+        if let Clock::Virtual(now) = &mut self.clock {
+            *now = (*now).max(deadline);
+        }
+    }
+
+    fn take_expired(&mut self) -> Vec<Waker> {
+        let now = self.now();
+        let mut wakers = Vec::new();
+        while let Some(entry) = self.entries.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            wakers.push(entry.remove());
+        }
+        wakers
+    }
+}